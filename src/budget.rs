@@ -0,0 +1,137 @@
+use crate::config::Config;
+use crate::frontier::Frontier;
+use crate::shutdown;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static SOLVER_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+// Stops the run once the time budget is used up. The solvers which are running are killed,
+// like on SIGINT, so the tree built so far is what gets written.
+pub fn start_clock(config: &Config) {
+    let Some(budget) = config.time_budget else {
+        return;
+    };
+    let deadline = Instant::now() + Duration::from_secs_f32(budget);
+    thread::spawn(move || {
+        while Instant::now() < deadline {
+            if shutdown::requested() {
+                return;
+            }
+            thread::sleep(shutdown::POLL_INTERVAL);
+        }
+        shutdown::stop(format!("the time budget of {budget} seconds is used up"));
+    });
+}
+
+// Counts a call which really runs the solver, so results from the cache are free.
+// The call which goes past the limit stops the run instead of running the solver.
+pub fn count_solver_call(config: &Config) {
+    let calls = SOLVER_CALLS.fetch_add(1, Ordering::SeqCst) + 1;
+    if let Some(max) = config.max_solver_calls.filter(|max| calls > *max) {
+        shutdown::stop(format!("the limit of {max} solver calls is reached"));
+    }
+}
+
+// Stops the run once the tree has enough leaves. Splits which finish at the same time as
+// the one reaching the limit can still go past it.
+pub fn check_leaves(config: &Config, frontier: &Frontier) {
+    if let Some(max) = config.max_leaves.filter(|max| frontier.len() >= *max) {
+        shutdown::stop(format!("the limit of {max} leaves is reached"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{install_cache, Cache};
+    use crate::cube::Cube;
+    use crate::runners::{run_solver, tree_gen, SolverResult};
+    use crate::test_utils::{isolated, test_config, CONSTANT_SOLVER};
+    use std::fs;
+
+    #[test]
+    fn time_budget_stops_slow_runs() {
+        if !isolated("budget::tests::time_budget_stops_slow_runs") {
+            return;
+        }
+        let config = test_config("time_budget", "#!/bin/sh\nsleep 30\n", "time budget: 0.5");
+        let start = Instant::now();
+        start_clock(&config);
+        assert_eq!(
+            run_solver(&config, &Cube(vec![1]), 60.0).unwrap(),
+            SolverResult::Timeout
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            shutdown::stop_reason(),
+            Some("the time budget of 0.5 seconds is used up")
+        );
+    }
+
+    #[test]
+    fn cache_hits_are_not_solver_calls() {
+        if !isolated("budget::tests::cache_hits_are_not_solver_calls") {
+            return;
+        }
+        install_cache(Cache::in_memory());
+        let config = test_config("max_solver_calls", CONSTANT_SOLVER, "max solver calls: 2");
+        for _ in 0..3 {
+            assert!(matches!(
+                run_solver(&config, &Cube(vec![1]), 5.0).unwrap(),
+                SolverResult::Finished(_)
+            ));
+        }
+        assert!(matches!(
+            run_solver(&config, &Cube(vec![2]), 5.0).unwrap(),
+            SolverResult::Finished(_)
+        ));
+        assert!(!shutdown::requested());
+
+        // the third real call stops the run instead of running the solver
+        assert_eq!(
+            run_solver(&config, &Cube(vec![-1]), 5.0).unwrap(),
+            SolverResult::Timeout
+        );
+        assert_eq!(shutdown::stop_reason(), Some("the limit of 2 solver calls is reached"));
+    }
+
+    #[test]
+    fn retries_are_solver_calls() {
+        if !isolated("budget::tests::retries_are_solver_calls") {
+            return;
+        }
+        let config = test_config(
+            "retry_calls",
+            "#!/bin/sh\nexit 1\n",
+            "solver retries: 5\nmax solver calls: 2",
+        );
+        assert_eq!(run_solver(&config, &Cube(vec![1]), 5.0).unwrap(), SolverResult::Timeout);
+        assert_eq!(shutdown::stop_reason(), Some("the limit of 2 solver calls is reached"));
+    }
+
+    #[test]
+    fn max_leaves_stops_the_tree() {
+        if !isolated("budget::tests::max_leaves_stops_the_tree") {
+            return;
+        }
+        // every literal of the cube takes a second off
+        let solver = concat!(
+            "#!/bin/sh\n",
+            "t=$((10 - $(grep -cE '^-?[0-9]+ 0$' \"$1\")))\n",
+            "echo 'SPLITS DATA' > \"$2\"\necho \"{\\\"time\\\": $t}\" >> \"$2\"\n",
+        );
+        let config = test_config("max_leaves", solver, "max leaves: 3");
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let root = Cube(Vec::new());
+        let frontier = Frontier::new(std::slice::from_ref(&root));
+        tree_gen(&config, &pool, &frontier, &root, 10.0, 30.0).unwrap();
+
+        assert_eq!(frontier.len(), 3);
+        assert_eq!(shutdown::stop_reason(), Some("the limit of 3 leaves is reached"));
+        let best_log = fs::read_to_string(format!("{}/best.log", config.output_dir)).unwrap();
+        assert_eq!(best_log.lines().count(), 4);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::config::Config;
+use crate::cube::Cube;
+use crate::runners::{check_metrics, SolverResult};
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use serde_json::{json, Value};
+
+static CACHE: OnceLock<Cache> = OnceLock::new();
+
+// Every solver call looks here first
+pub fn cache() -> Option<&'static Cache> {
+    CACHE.get()
+}
+
+pub fn install_cache(cache: Cache) {
+    if CACHE.set(cache).is_err() {
+        panic!("Tried to install a second cache");
+    }
+}
+
+// 64 bit FNV-1a, which is stable across runs and platforms
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    Finished(HashMap<String, f32>),
+    // The longest timeout the cube has been seen to time out at
+    Timeout(f32),
+}
+
+// Solver results by the canonical form of their cube, so a set of literals is only run once.
+// With a cache dir, results are also appended to `results.jsonl` there and shared by every run
+// on the same formula with the same solver. The file holds the results of every formula and
+// solver, so several runs can use the same cache dir.
+pub struct Cache {
+    entries: Mutex<HashMap<Cube, Entry>>,
+    file: Option<CacheFile>,
+}
+
+struct CacheFile {
+    formula: String,
+    solver: String,
+    file: Mutex<File>,
+}
+
+// Keeps whichever entry tells more
+fn merge(old: Option<&Entry>, new: Entry) -> Entry {
+    match (old, new) {
+        (Some(Entry::Finished(m)), _) => Entry::Finished(m.clone()),
+        (Some(Entry::Timeout(t)), Entry::Timeout(new_t)) => Entry::Timeout(t.max(new_t)),
+        (_, new) => new,
+    }
+}
+
+impl Cache {
+    // Only remembers results for this run
+    pub fn in_memory() -> Cache {
+        Cache { entries: Mutex::new(HashMap::new()), file: None }
+    }
+
+    pub fn open(dir: &str, config: &Config) -> Result<Cache, io::Error> {
+        fs::create_dir_all(dir)?;
+        let formula = format!(
+            "{:016x}",
+            fnv1a(config.cnf.extend_cube_str(&Cube(Vec::new())).as_bytes())
+        );
+        let mut solver_id = config.solver.as_bytes().to_vec();
+        solver_id.extend(fs::read(&config.solver)?);
+        let solver = format!("{:016x}", fnv1a(&solver_id));
+
+        let path = Path::new(dir).join("results.jsonl");
+        let mut entries = HashMap::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                // a run which was killed mid-write leaves a broken last line
+                let Ok(record) = serde_json::from_str::<Value>(&line?) else {
+                    continue;
+                };
+                if record["formula"] != formula.as_str() || record["solver"] != solver.as_str() {
+                    continue;
+                }
+                if let Some((cube, entry)) = decode_entry(&record) {
+                    let merged = merge(entries.get(&cube), entry);
+                    entries.insert(cube, merged);
+                }
+            }
+        }
+
+        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+        Ok(Cache {
+            entries: Mutex::new(entries),
+            file: Some(CacheFile { formula, solver, file: Mutex::new(file) }),
+        })
+    }
+
+    // A finished result is reused if the solver took no longer than `timeout_time`,
+    // and a timeout if the earlier run had at least as much time. The metrics are checked
+    // again, since the run which stored them might have used another metric or cutoff.
+    pub fn lookup(&self, config: &Config, cube: &Cube, timeout_time: f32) -> Option<SolverResult> {
+        match self.entries.lock().unwrap().get(&cube.canonical())? {
+            Entry::Finished(metrics) if metrics.get("time").is_some_and(|t| *t <= timeout_time) => {
+                Some(check_metrics(config, metrics.clone()))
+            }
+            Entry::Finished(_) => Some(SolverResult::Timeout),
+            Entry::Timeout(t) if timeout_time <= *t => Some(SolverResult::Timeout),
+            Entry::Timeout(_) => None,
+        }
+    }
+
+    // Only finished runs and timeouts are kept. Failures might not happen again.
+    pub fn store(&self, cube: &Cube, timeout_time: f32, res: &SolverResult) -> Result<(), io::Error> {
+        let entry = match res {
+            SolverResult::Finished(metrics) => Entry::Finished(metrics.clone()),
+            SolverResult::Timeout => Entry::Timeout(timeout_time),
+            _ => return Ok(()),
+        };
+        let cube = cube.canonical();
+        let record = self.file.as_ref().map(|file| {
+            let mut record = match &entry {
+                Entry::Finished(metrics) => json!({"status": "finished", "metrics": metrics}),
+                Entry::Timeout(t) => json!({"status": "timeout", "timeout": t}),
+            };
+            record["formula"] = json!(file.formula);
+            record["solver"] = json!(file.solver);
+            record["cube"] = json!(cube.0);
+            record
+        });
+
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let merged = merge(entries.get(&cube), entry);
+            entries.insert(cube, merged);
+        }
+        match (&self.file, record) {
+            (Some(file), Some(record)) => file.file.lock().unwrap().write_all(format!("{record}\n").as_bytes()),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn decode_entry(record: &Value) -> Option<(Cube, Entry)> {
+    let cube = Cube(serde_json::from_value(record["cube"].clone()).ok()?).canonical();
+    let entry = match record["status"].as_str()? {
+        "finished" => Entry::Finished(serde_json::from_value(record["metrics"].clone()).ok()?),
+        "timeout" => Entry::Timeout(record["timeout"].as_f64()? as f32),
+        _ => return None,
+    };
+    Some((cube, entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runners::SolverFailure;
+    use crate::test_utils::{test_config, CONSTANT_SOLVER};
+
+    fn finished(time: f32) -> SolverResult {
+        SolverResult::Finished(HashMap::from([("time".to_string(), time)]))
+    }
+
+    #[test]
+    fn fnv_is_stable() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn results_are_reused_across_runs() {
+        let config = test_config("cache", CONSTANT_SOLVER, "");
+        let dir = format!("{}/cache", config.output_dir);
+        let cache = Cache::open(&dir, &config).unwrap();
+        cache.store(&Cube(vec![2, -1]), 10.0, &finished(3.0)).unwrap();
+        cache.store(&Cube(vec![2]), 5.0, &SolverResult::Timeout).unwrap();
+        cache.store(&Cube(vec![-2]), 5.0, &SolverResult::MemoryOut).unwrap();
+        drop(cache);
+
+        let cache = Cache::open(&dir, &config).unwrap();
+        // the order of the literals doesn't matter
+        assert_eq!(cache.lookup(&config, &Cube(vec![-1, 2]), 10.0), Some(finished(3.0)));
+        assert_eq!(
+            cache.lookup(&config, &Cube(vec![-1, 2]), 2.0),
+            Some(SolverResult::Timeout)
+        );
+        assert_eq!(cache.lookup(&config, &Cube(vec![2]), 4.0), Some(SolverResult::Timeout));
+        assert_eq!(cache.lookup(&config, &Cube(vec![2]), 6.0), None);
+        assert_eq!(cache.lookup(&config, &Cube(vec![-2]), 6.0), None);
+
+        // another solver doesn't see the results
+        let mut other = config.clone();
+        other.solver = test_config("cache_other", "#!/bin/sh\nexit 1\n", "").solver;
+        let cache = Cache::open(&dir, &other).unwrap();
+        assert_eq!(cache.lookup(&other, &Cube(vec![-1, 2]), 10.0), None);
+    }
+
+    #[test]
+    fn cached_metrics_are_checked() {
+        let config = test_config("cache_metrics", CONSTANT_SOLVER, "");
+        let cache = Cache::in_memory();
+        cache.store(&Cube(vec![1]), 10.0, &finished(3.0)).unwrap();
+
+        // a later run which measures something the cached run didn't report
+        let other = test_config("cache_metrics_other", CONSTANT_SOLVER, "evaluation metric: ticks");
+        assert_eq!(
+            cache.lookup(&other, &Cube(vec![1]), 10.0),
+            Some(SolverResult::Failed(SolverFailure::MissingMetric("ticks".to_string())))
+        );
+        assert_eq!(cache.lookup(&config, &Cube(vec![1]), 10.0), Some(finished(3.0)));
+    }
+
+    #[test]
+    fn in_memory_results() {
+        let config = test_config("cache_in_memory", CONSTANT_SOLVER, "");
+        let cache = Cache::in_memory();
+        cache.store(&Cube(vec![3, 1]), 10.0, &SolverResult::Timeout).unwrap();
+        cache.store(&Cube(vec![1, 3]), 2.0, &SolverResult::Timeout).unwrap();
+        assert_eq!(
+            cache.lookup(&config, &Cube(vec![1, 3]), 10.0),
+            Some(SolverResult::Timeout)
+        );
+        cache.store(&Cube(vec![1, 3]), 20.0, &finished(15.0)).unwrap();
+        assert_eq!(cache.lookup(&config, &Cube(vec![3, 1]), 20.0), Some(finished(15.0)));
+    }
+}
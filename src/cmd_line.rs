@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 pub struct Args {
@@ -7,6 +7,35 @@ pub struct Args {
 
     #[arg(long, default_value_t = false)]
     pub no_confirm: bool,
+
+    #[command(subcommand)]
+    pub mode: Option<Mode>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Mode {
+    /// Run the tree search, handing every solver call to remote workers
+    Coordinator {
+        #[arg(long, default_value = "0.0.0.0:7878")]
+        listen: String,
+    },
+    /// Run the solver calls handed out by a coordinator
+    Worker {
+        #[arg(long)]
+        connect: String,
+    },
+    /// Split the leaves of an earlier run whose metric is worse than the threshold further
+    Refine {
+        #[arg(long)]
+        run: String,
+        #[arg(long)]
+        threshold: f32,
+    },
+    /// Run the solver on every cube of an existing icnf or cube file instead of building a tree
+    Evaluate {
+        #[arg(long)]
+        cubes: String,
+    },
 }
 
 pub fn get_args() -> Args {
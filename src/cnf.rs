@@ -110,6 +110,15 @@ impl Cnf {
         cnf_copy.extend_cube(cube);
         cnf_copy.to_string()
     }
+
+    // The formula as the start of an incremental CNF, which is followed by cubes
+    pub fn to_inccnf_string(&self) -> String {
+        let mut output_str = "p inccnf\n".to_string();
+        for Clause(v) in &self.clauses {
+            output_str.push_str(&format!("{} 0\n", &v.iter().map(|x| x.to_string()).join(" ")));
+        }
+        output_str
+    }
 }
 
 #[cfg(test)]
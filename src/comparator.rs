@@ -0,0 +1,206 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+// A comparator is written as a goal followed by an aggregate, e.g. `minmax`, `maxmin`,
+// `minsum`, `maxproduct` or `minp90`. The aggregate turns the metrics of one class
+// of sibling cubes into a single score, and the goal says which score wins.
+#[derive(Debug, Clone, Copy)]
+pub struct Comparator {
+    pub goal: Goal,
+    pub aggregate: Aggregate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Goal {
+    Minimize,
+    Maximize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Aggregate {
+    Max,
+    Min,
+    Sum,
+    Mean,
+    Product,
+    // Nearest-rank percentile, in (0, 100]
+    Percentile(f32),
+    Custom(&'static str, AggregateFn),
+}
+
+pub type AggregateFn = fn(&[f32]) -> f32;
+
+static CUSTOM_AGGREGATES: RwLock<Vec<(&'static str, AggregateFn)>> = RwLock::new(Vec::new());
+
+// Makes `min<name>` and `max<name>` available as comparators. This has to happen before
+// the config is parsed. Registering a name twice replaces the earlier function.
+#[allow(dead_code)]
+pub fn register_aggregate(name: &'static str, f: AggregateFn) {
+    let mut custom = CUSTOM_AGGREGATES.write().unwrap();
+    custom.retain(|(n, _)| *n != name);
+    custom.push((name, f));
+}
+
+impl Aggregate {
+    pub fn apply(&self, metrics: &[f32]) -> f32 {
+        match self {
+            Aggregate::Max => metrics.iter().copied().reduce(f32::max).unwrap(),
+            Aggregate::Min => metrics.iter().copied().reduce(f32::min).unwrap(),
+            Aggregate::Sum => metrics.iter().sum(),
+            Aggregate::Mean => metrics.iter().sum::<f32>() / metrics.len() as f32,
+            Aggregate::Product => metrics.iter().product(),
+            Aggregate::Percentile(p) => {
+                let mut sorted = metrics.to_vec();
+                sorted.sort_by(f32::total_cmp);
+                let rank = (p / 100.0 * sorted.len() as f32).ceil() as usize;
+                sorted[rank.clamp(1, sorted.len()) - 1]
+            }
+            Aggregate::Custom(_, f) => f(metrics),
+        }
+    }
+}
+
+impl Comparator {
+    pub const MIN_OF_MAX: Comparator = Comparator { goal: Goal::Minimize, aggregate: Aggregate::Max };
+
+    pub fn score(&self, metrics: &[f32]) -> f32 {
+        self.aggregate.apply(metrics)
+    }
+
+    // Orders scores from best to worst
+    pub fn cmp(&self, x: f32, y: f32) -> Ordering {
+        match self.goal {
+            Goal::Minimize => x.total_cmp(&y),
+            Goal::Maximize => y.total_cmp(&x),
+        }
+    }
+
+    // Whether `x` is strictly better than `bound`
+    pub fn beats(&self, x: f32, bound: f32) -> bool {
+        match self.goal {
+            Goal::Minimize => x < bound,
+            Goal::Maximize => x > bound,
+        }
+    }
+
+    // A score every real score beats
+    pub fn worst(&self) -> f32 {
+        match self.goal {
+            Goal::Minimize => f32::MAX,
+            Goal::Maximize => f32::MIN,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComparatorError(pub String);
+
+impl FromStr for Comparator {
+    type Err = ComparatorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (goal, rest) = if let Some(rest) = s.strip_prefix("min") {
+            (Goal::Minimize, rest)
+        } else if let Some(rest) = s.strip_prefix("max") {
+            (Goal::Maximize, rest)
+        } else {
+            return Err(ComparatorError(format!("{s} does not start with 'min' or 'max'")));
+        };
+        let aggregate = match rest {
+            "max" => Aggregate::Max,
+            "min" => Aggregate::Min,
+            "sum" => Aggregate::Sum,
+            "mean" => Aggregate::Mean,
+            "product" => Aggregate::Product,
+            _ if rest.starts_with('p') && rest[1..].parse::<f32>().is_ok() => {
+                let p = rest[1..].parse::<f32>().unwrap();
+                if !(p > 0.0 && p <= 100.0) {
+                    return Err(ComparatorError(format!("The percentile {p} is not in (0, 100]")));
+                }
+                Aggregate::Percentile(p)
+            }
+            _ => match CUSTOM_AGGREGATES.read().unwrap().iter().find(|(n, _)| *n == rest) {
+                Some((name, f)) => Aggregate::Custom(name, *f),
+                None => return Err(ComparatorError(format!("Unknown aggregate '{rest}'"))),
+            },
+        };
+        Ok(Comparator { goal, aggregate })
+    }
+}
+
+impl fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Aggregate::Max => write!(f, "maxs"),
+            Aggregate::Min => write!(f, "mins"),
+            Aggregate::Sum => write!(f, "sums"),
+            Aggregate::Mean => write!(f, "means"),
+            Aggregate::Product => write!(f, "products"),
+            Aggregate::Percentile(p) => write!(f, "{p}th percentiles"),
+            Aggregate::Custom(name, _) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl fmt::Display for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.goal {
+            Goal::Minimize => write!(f, "min of {}", self.aggregate),
+            Goal::Maximize => write!(f, "max of {}", self.aggregate),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(s: &str, metrics: &[f32]) -> f32 {
+        s.parse::<Comparator>().unwrap().score(metrics)
+    }
+
+    #[test]
+    fn builtin_aggregates() {
+        let metrics = [4.0, 1.0, 2.0, 8.0];
+        assert_eq!(score("minmax", &metrics), 8.0);
+        assert_eq!(score("maxmin", &metrics), 1.0);
+        assert_eq!(score("minsum", &metrics), 15.0);
+        assert_eq!(score("minmean", &metrics), 3.75);
+        assert_eq!(score("maxproduct", &metrics), 64.0);
+        assert_eq!(score("minp50", &metrics), 2.0);
+        assert_eq!(score("minp75", &metrics), 4.0);
+        assert_eq!(score("minp100", &metrics), 8.0);
+        assert_eq!(score("minp1", &metrics), 1.0);
+    }
+
+    #[test]
+    fn goals() {
+        let min: Comparator = "minsum".parse().unwrap();
+        let max: Comparator = "maxsum".parse().unwrap();
+        assert!(min.beats(1.0, 2.0) && !min.beats(2.0, 2.0));
+        assert!(max.beats(2.0, 1.0) && !max.beats(1.0, 1.0));
+        assert_eq!(min.cmp(1.0, 2.0), Ordering::Less);
+        assert_eq!(max.cmp(1.0, 2.0), Ordering::Greater);
+        assert!(min.beats(1e30, min.worst()) && max.beats(-1e30, max.worst()));
+    }
+
+    #[test]
+    fn custom_aggregate() {
+        fn spread(metrics: &[f32]) -> f32 {
+            Aggregate::Max.apply(metrics) - Aggregate::Min.apply(metrics)
+        }
+        assert!("minspread".parse::<Comparator>().is_err());
+        register_aggregate("spread", spread);
+        assert_eq!(score("minspread", &[3.0, 7.0, 5.0]), 4.0);
+        assert_eq!("maxspread".parse::<Comparator>().unwrap().to_string(), "max of spread");
+    }
+
+    #[test]
+    fn bad_comparators() {
+        for bad in ["", "sum", "minmedian", "minp0", "minp101", "minp", "max max"] {
+            assert!(bad.parse::<Comparator>().is_err(), "{bad} should not parse");
+        }
+    }
+}
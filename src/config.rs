@@ -1,17 +1,16 @@
 use crate::cnf::Cnf;
-use crate::cube::Cube;
+use crate::comparator::{Aggregate, Comparator, ComparatorError, Goal};
+use crate::cube::{find_overlap, read_cube_file, Cube};
+use crate::expr::{Expr, ExprError};
+use crate::limits::usable_cores;
 use crate::wcnf::Wcnf;
 use is_executable::IsExecutable;
+use itertools::Itertools;
 use std::path::Path;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::{fmt, fs, io};
 
-#[derive(Debug)]
-pub enum Comparator {
-    MaxOfMin,
-    MinOfMax,
-}
-
 #[derive(Debug)]
 pub enum SatType {
     Cnf(Cnf),
@@ -43,40 +42,47 @@ impl FromStr for SatType {
     }
 }
 
-impl fmt::Display for Comparator {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            Comparator::MaxOfMin => write!(f, "max of mins"),
-            Comparator::MinOfMax => write!(f, "min of maxs"),
-        }
-    }
-}
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     pub variables: Vec<u32>,
     pub multitree_variables: Option<Vec<u32>>,
+    pub starting_cubes: Option<Vec<Cube>>,
     pub comparator: Comparator,
     pub timeout: u32,
     pub solver: String,
-    pub cnf: SatType,
+    pub cnf: Arc<SatType>,
     pub output_dir: String,
     pub tmp_dir: String,
-    pub evaluation_metric: String,
+    pub evaluation_metric: Expr,
     pub thread_count: usize,
     pub search_depth: u32,
     pub preserve_cnf: bool,
     pub preserve_logs: bool,
     pub cutoff_proportion: f32,
     pub time_proportion: f32,
-    pub cutoff: f32,
+    pub cutoff: Expr,
     pub preproc_count: Option<usize>,
+    pub memory_limit: Option<u64>,
+    pub pin_cores: bool,
+    pub kill_grace_period: f32,
+    pub solver_retries: u32,
+    pub conquer_workers: Option<usize>,
+    pub beam_width: usize,
+    pub successive_halving: Option<f32>,
+    pub tighten_timeouts: bool,
+    pub cache_dir: Option<String>,
+    pub full_inccnf: bool,
+    pub coarsen_threshold: Option<f32>,
+    pub target_cubes: Option<usize>,
+    pub time_budget: Option<f32>,
+    pub max_leaves: Option<usize>,
+    pub max_solver_calls: Option<usize>,
     pub debug: bool,
 }
 
 impl fmt::Display for Config {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut vec_output = Vec::with_capacity(18);
+        let mut vec_output = Vec::with_capacity(34);
         let variable_string = if self.variables.len() <= 30 {
             format!("{:?}", self.variables)
         } else {
@@ -84,10 +90,16 @@ impl fmt::Display for Config {
         };
         vec_output.push(format!("          Variables: {variable_string}"));
         vec_output.push(format!("Multitree Variables: {:?}", self.multitree_variables));
+        let starting_cubes_string = match &self.starting_cubes {
+            Some(cubes) if cubes.len() <= 30 => format!("[{}]", cubes.iter().join(", ")),
+            Some(cubes) => format!("<{} cubes>", cubes.len()),
+            None => "None".to_string(),
+        };
+        vec_output.push(format!("     Starting Cubes: {starting_cubes_string}"));
         vec_output.push(format!("         Comparator: {}", self.comparator));
         vec_output.push(format!("            Timeout: {}", self.timeout));
         vec_output.push(format!("             Solver: {}", self.solver));
-        match self.cnf {
+        match *self.cnf {
             SatType::Cnf(_) => vec_output.push("           SAT Type: CNF".to_string()),
             SatType::Wcnf(_) => vec_output.push("          SAT Type: WCNF".to_string()),
         }
@@ -102,6 +114,21 @@ impl fmt::Display for Config {
         vec_output.push(format!("    Time Proportion: {}", self.time_proportion));
         vec_output.push(format!("             Cutoff: {}", self.cutoff));
         vec_output.push(format!("   Preprocess Count: {:?}", self.preproc_count));
+        vec_output.push(format!("  Memory Limit (MB): {:?}", self.memory_limit));
+        vec_output.push(format!("          Pin Cores: {}", self.pin_cores));
+        vec_output.push(format!("  Kill Grace Period: {}", self.kill_grace_period));
+        vec_output.push(format!("     Solver Retries: {}", self.solver_retries));
+        vec_output.push(format!("    Conquer Workers: {:?}", self.conquer_workers));
+        vec_output.push(format!("         Beam Width: {}", self.beam_width));
+        vec_output.push(format!("Successive Halving: {:?}", self.successive_halving));
+        vec_output.push(format!("   Tighten Timeouts: {}", self.tighten_timeouts));
+        vec_output.push(format!("    Cache Directory: {:?}", self.cache_dir));
+        vec_output.push(format!("        Full Inccnf: {}", self.full_inccnf));
+        vec_output.push(format!("  Coarsen Threshold: {:?}", self.coarsen_threshold));
+        vec_output.push(format!("       Target Cubes: {:?}", self.target_cubes));
+        vec_output.push(format!("        Time Budget: {:?}", self.time_budget));
+        vec_output.push(format!("         Max Leaves: {:?}", self.max_leaves));
+        vec_output.push(format!("   Max Solver Calls: {:?}", self.max_solver_calls));
         vec_output.push(format!("         Debug Mode: {}", self.debug));
 
         let output_str = vec_output.join("\n");
@@ -128,7 +155,7 @@ impl Config {
     pub fn parse_config(config_string: &str) -> Result<Self, ConfigError> {
         let trimmed_cfg_string = config_string.trim();
         let mut variable_opt = None;
-        let mut comparator = Comparator::MinOfMax;
+        let mut comparator = Comparator::MIN_OF_MAX;
         let mut solver_opt = None;
         let mut timeout = 600;
         let mut cnf_opt = None;
@@ -138,6 +165,7 @@ impl Config {
         let mut preserve_cnf = false;
         let mut preserve_logs = false;
         let mut multitree_variables = None;
+        let mut starting_cubes = None;
 
         let mut search_depth = 1;
         let mut thread_count = rayon::current_num_threads();
@@ -146,6 +174,21 @@ impl Config {
         let mut time_proportion = 1.0;
 
         let mut preproc_count = None;
+        let mut conquer_workers = None;
+        let mut beam_width = 1;
+        let mut successive_halving = None;
+        let mut tighten_timeouts = false;
+        let mut cache_dir = None;
+        let mut full_inccnf = false;
+        let mut coarsen_threshold = None;
+        let mut target_cubes = None;
+        let mut time_budget = None;
+        let mut max_leaves = None;
+        let mut max_solver_calls = None;
+        let mut memory_limit = None;
+        let mut pin_cores = false;
+        let mut kill_grace_period = 5.0;
+        let mut solver_retries = 1;
         let mut debug = false;
 
         for line in trimmed_cfg_string.lines() {
@@ -198,15 +241,35 @@ impl Config {
                     }
                     multitree_variables = Some(variable_vec);
                 }
+                "starting cubes" => {
+                    let cubes = match read_cube_file(argument) {
+                        Ok(cubes) => cubes,
+                        Err(e) => return Err(ConfigError(format!("Failed to read starting cubes from {argument}: {e}"))),
+                    };
+                    if cubes.is_empty() {
+                        return Err(ConfigError(format!("There are no 'a ... 0' lines of starting cubes in {argument}.")));
+                    }
+                    if let Some(cube) = cubes.iter().find(|cube| cube.0.is_empty() || cube.is_contradictory()) {
+                        return Err(ConfigError(format!(
+                            "The starting cube 'a {}0' is empty or contradictory.",
+                            cube.0.iter().map(|lit| format!("{lit} ")).join("")
+                        )));
+                    }
+                    if let Some((i, j)) = find_overlap(&cubes) {
+                        return Err(ConfigError(format!(
+                            "The starting cubes {} and {} overlap. Please make sure every pair of starting cubes has a conflicting literal.",
+                            cubes[i], cubes[j]
+                        )));
+                    }
+                    starting_cubes = Some(cubes);
+                }
 
-                "comparator" => match argument {
-                    "minmax" => comparator = Comparator::MinOfMax,
-                    "maxmin" => comparator = Comparator::MaxOfMin,
-                    _ => {
-                        return Err(ConfigError(
-                            "Failed to recognize Comparison Operator. Please use either 'minmax' or 'maxin'."
-                                .to_string(),
-                        ));
+                "comparator" => match argument.parse::<Comparator>() {
+                    Ok(c) => comparator = c,
+                    Err(ComparatorError(e)) => {
+                        return Err(ConfigError(format!(
+                            "Failed to recognize Comparison Operator: {e}. Please use 'min' or 'max' followed by one of 'max', 'min', 'sum', 'mean', 'product' or 'p<percentile>', e.g. 'minmax' or 'maxmin'."
+                        )));
                     }
                 },
                 "timeout" => match argument.parse::<u32>() {
@@ -237,19 +300,27 @@ impl Config {
                     }
                     let cnf_string = fs::read_to_string(cnf_path)?;
                     match cnf_string.parse::<SatType>() {
-                        Ok(s) => cnf_opt = Some(s),
+                        Ok(s) => cnf_opt = Some(Arc::new(s)),
                         Err(SatTypeError(s)) => return Err(ConfigError(format!("Failed to parse: {s}"))),
                     }
                 }
                 "output dir" => {
                     output_dir = argument.to_string();
                 }
+                "cache dir" => {
+                    cache_dir = Some(argument.to_string());
+                }
                 "tmp dir" => {
                     tmp_dir = argument.to_string();
                 }
-                "evaluation metric" => {
-                    evaluation_metric_opt = Some(argument.to_string());
-                }
+                "evaluation metric" => match argument.parse::<Expr>() {
+                    Ok(e) => evaluation_metric_opt = Some(e),
+                    Err(ExprError(e)) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as an evaluation metric: {e}."
+                        )))
+                    }
+                },
                 "search depth" => {
                     match argument.parse() {
                         Ok(u) => {
@@ -314,16 +385,16 @@ impl Config {
                         )))
                     }
                 },
-                "cutoff" => match argument.parse() {
-                    Ok(f) => {
-                        if f <= 0.0 {
+                "cutoff" => match argument.parse::<Expr>() {
+                    Ok(e) => {
+                        if let Some(f) = e.constant().filter(|f| *f <= 0.0) {
                             return Err(ConfigError(format!("Cutoff {f} needs to be a positive float.")));
                         }
-                        cutoff_opt = Some(f);
+                        cutoff_opt = Some(e);
                     }
-                    Err(_) => {
+                    Err(ExprError(e)) => {
                         return Err(ConfigError(format!(
-                            "Cannot parse {argument} as a cutoff. Please make sure it is a positive float"
+                            "Cannot parse {argument} as a cutoff: {e}. Please make sure it is a positive float or an expression over the metrics"
                         )))
                     }
                 },
@@ -350,6 +421,168 @@ impl Config {
                         )))
                     }
                 },
+                "memory limit" => match argument.parse() {
+                    Ok(m) => {
+                        if m == 0 {
+                            return Err(ConfigError("0 is not a valid memory limit.".to_string()));
+                        }
+                        memory_limit = Some(m);
+                    }
+                    Err(_) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as a memory limit. Please provide a positive integer number of megabytes."
+                        )))
+                    }
+                },
+                "pin cores" => match argument.parse() {
+                    Ok(b) => pin_cores = b,
+                    Err(_) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as a boolean for pinning cores."
+                        )))
+                    }
+                },
+                "kill grace period" => match argument.parse::<f32>() {
+                    Ok(f) => {
+                        if f < 0.0 || !f.is_finite() {
+                            return Err(ConfigError(format!(
+                                "Kill grace period {f} needs to be a non-negative number of seconds."
+                            )));
+                        }
+                        kill_grace_period = f
+                    }
+                    Err(_) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as a kill grace period. Please provide a number of seconds."
+                        )))
+                    }
+                },
+                "solver retries" => match argument.parse() {
+                    Ok(n) => solver_retries = n,
+                    Err(_) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as a number of retries. Please make sure it is a non-negative integer."
+                        )))
+                    }
+                },
+                "conquer workers" => match argument.parse() {
+                    Ok(w) => {
+                        if w == 0 {
+                            return Err(ConfigError("0 is not a valid number of conquer workers.".to_string()));
+                        }
+                        conquer_workers = Some(w);
+                    }
+                    Err(_) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as the number of conquer workers. Please make sure it is a positive integer."
+                        )))
+                    }
+                },
+                "beam width" => match argument.parse() {
+                    Ok(w) => {
+                        if w == 0 {
+                            return Err(ConfigError("0 is not a valid beam width.".to_string()));
+                        }
+                        beam_width = w;
+                    }
+                    Err(_) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as a beam width. Please make sure it is a positive integer."
+                        )))
+                    }
+                },
+                "successive halving" => match argument.parse::<f32>() {
+                    Ok(f) => {
+                        // written so that NaN fails as well
+                        if !(f > 0.0 && f < 1.0) {
+                            return Err(ConfigError(format!(
+                                "Successive halving {f} needs to be a proportion of the timeout between 0 and 1."
+                            )));
+                        }
+                        successive_halving = Some(f);
+                    }
+                    Err(_) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as the initial budget for successive halving. Please make sure it is a float between 0 and 1."
+                        )))
+                    }
+                },
+                "tighten timeouts" => match argument.parse() {
+                    Ok(b) => tighten_timeouts = b,
+                    Err(_) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as a boolean for tightening timeouts."
+                        )))
+                    }
+                },
+                "full inccnf" => match argument.parse() {
+                    Ok(b) => full_inccnf = b,
+                    Err(_) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as a boolean for writing a full inccnf."
+                        )))
+                    }
+                },
+                "coarsen threshold" => match argument.parse::<f32>() {
+                    Ok(f) => {
+                        if f <= 0.0 || f.is_nan() {
+                            return Err(ConfigError(format!("Coarsen threshold {f} needs to be positive.")));
+                        }
+                        coarsen_threshold = Some(f);
+                    }
+                    Err(_) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as a coarsen threshold. Please make sure it is a positive float."
+                        )))
+                    }
+                },
+                "target cubes" => match argument.parse() {
+                    Ok(n) => {
+                        if n == 0 {
+                            return Err(ConfigError("0 is not a valid number of target cubes.".to_string()));
+                        }
+                        target_cubes = Some(n);
+                    }
+                    Err(_) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as the number of target cubes. Please make sure it is a positive integer."
+                        )))
+                    }
+                },
+                "time budget" => match argument.parse::<f32>() {
+                    Ok(f) => {
+                        if f <= 0.0 || !f.is_finite() {
+                            return Err(ConfigError(format!("Time budget {f} needs to be a positive number of seconds.")));
+                        }
+                        time_budget = Some(f);
+                    }
+                    Err(_) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as a time budget. Please provide a number of seconds."
+                        )))
+                    }
+                },
+                "max leaves" => match argument.parse() {
+                    Ok(n) => {
+                        if n == 0 {
+                            return Err(ConfigError("0 is not a valid maximum number of leaves.".to_string()));
+                        }
+                        max_leaves = Some(n);
+                    }
+                    Err(_) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as the maximum number of leaves. Please make sure it is a positive integer."
+                        )))
+                    }
+                },
+                "max solver calls" => match argument.parse() {
+                    Ok(n) => max_solver_calls = Some(n),
+                    Err(_) => {
+                        return Err(ConfigError(format!(
+                            "Cannot parse {argument} as the maximum number of solver calls. Please make sure it is a non-negative integer."
+                        )))
+                    }
+                },
                 "debug" => match argument.parse() {
                     Ok(b) => debug = b,
                     Err(_) => {
@@ -364,6 +597,11 @@ impl Config {
             }
         }
 
+        // Aiming for a number of cubes takes the place of the cutoff, so it is never consulted
+        if target_cubes.is_some() && cutoff_opt.is_none() {
+            cutoff_opt = Some(Expr::from_str("0").unwrap());
+        }
+
         let (variables, solver, cnf, evaluation_metric, cutoff) =
             match (variable_opt, solver_opt, cnf_opt, evaluation_metric_opt, cutoff_opt) {
                 (None, _, _, _, _) => return Err(ConfigError("Please provide variables in the config.".to_string())),
@@ -390,9 +628,65 @@ impl Config {
                 (Some(v), Some(s), Some(c), Some(em), Some(ct)) => (v, s, c, em, ct),
             };
 
+        if multitree_variables.is_some() && starting_cubes.is_some() {
+            return Err(ConfigError(
+                "Multitree variables and starting cubes cannot be used together.".to_string(),
+            ));
+        }
+
+        if conquer_workers.is_some() && comparator.goal != Goal::Minimize {
+            return Err(ConfigError(
+                "Estimating the makespan needs a comparator which minimizes, like 'minmax'.".to_string(),
+            ));
+        }
+
+        if conquer_workers.is_some() && beam_width > 1 {
+            return Err(ConfigError(
+                "A beam width above 1 cannot be combined with conquer workers.".to_string(),
+            ));
+        }
+
+        if tighten_timeouts {
+            if !evaluation_metric.is_metric("time")
+                || comparator.goal != Goal::Minimize
+                || !matches!(comparator.aggregate, Aggregate::Max | Aggregate::Sum)
+            {
+                return Err(ConfigError(
+                    "Tightening timeouts needs 'time' as the evaluation metric and 'minmax' or 'minsum' as the comparator."
+                        .to_string(),
+                ));
+            }
+            if successive_halving.is_some() {
+                return Err(ConfigError(
+                    "Tightening timeouts cannot be combined with successive halving.".to_string(),
+                ));
+            }
+        }
+
+        if coarsen_threshold.is_some() && comparator.goal != Goal::Minimize {
+            return Err(ConfigError(
+                "Coarsening adds up the metrics of leaves, so it needs a comparator which minimizes, like 'minmax'."
+                    .to_string(),
+            ));
+        }
+
+        if full_inccnf && matches!(*cnf, SatType::Wcnf(_)) {
+            return Err(ConfigError(
+                "A full inccnf can only be written for a CNF, not a WCNF.".to_string(),
+            ));
+        }
+
+        if pin_cores && thread_count > usable_cores().len() {
+            return Err(ConfigError(format!(
+                "Cannot pin {thread_count} threads to dedicated cores when only {} cores are available.",
+                usable_cores().len()
+            )));
+        }
+
         Ok(Config {
             variables,
             multitree_variables,
+            starting_cubes,
             comparator,
             timeout,
             solver,
@@ -408,7 +702,43 @@ impl Config {
             cutoff,
             preserve_logs,
             preproc_count,
+            memory_limit,
+            pin_cores,
+            kill_grace_period,
+            solver_retries,
+            conquer_workers,
+            beam_width,
+            successive_halving,
+            tighten_timeouts,
+            cache_dir,
+            full_inccnf,
+            coarsen_threshold,
+            target_cubes,
+            time_budget,
+            max_leaves,
+            max_solver_calls,
             debug,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{test_config_string, CONSTANT_SOLVER};
+
+    #[test]
+    fn nan_coarsen_threshold_is_rejected() {
+        let config_string = test_config_string("coarsen_nan", CONSTANT_SOLVER, "coarsen threshold: nan");
+        let Err(ConfigError(message)) = Config::parse_config(&config_string) else {
+            panic!("a NaN coarsen threshold was accepted");
+        };
+        assert_eq!(message, "Coarsen threshold NaN needs to be positive.");
+
+        let config_string = test_config_string("coarsen_positive", CONSTANT_SOLVER, "coarsen threshold: 0.5");
+        assert_eq!(
+            Config::parse_config(&config_string).unwrap().coarsen_threshold,
+            Some(0.5)
+        );
+    }
+}
@@ -1,7 +1,15 @@
+use crate::cache::fnv1a;
 use itertools::Itertools;
-use std::{fmt::Display, num::ParseIntError, str::FromStr};
+use std::collections::{HashMap, HashSet};
+use std::{fmt::Display, fs, io, num::ParseIntError, str::FromStr};
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+// Cube names longer than this are replaced by their hash in file names, which keeps
+// file names below the usual limit of 255 bytes with room for an extension
+const MAX_FILE_NAME_LEN: usize = 200;
+
+// The literals in the order they were added to the tree. Two cubes with the same literals
+// in another order are different here, but have the same `canonical` form.
+#[derive(Debug, PartialEq, Eq, Clone, Hash, PartialOrd, Ord)]
 pub struct Cube(pub Vec<i32>);
 
 impl FromStr for Cube {
@@ -39,9 +47,9 @@ impl Cube {
     // this is n^2, but faster for small vectors (like cubes)
     // https://stackoverflow.com/a/64227550/10833363
     pub fn subsumes(&self, Cube(cv1): &Cube) -> bool {
-        return self.0.iter().all(|var| cv1.contains(var));
+        self.0.iter().all(|var| cv1.contains(var))
     }
-    
+
     pub fn extend(&self, var: u32) -> (Cube, Cube) {
         let mut v1 = self.0.clone();
         let mut v2 = self.0.clone();
@@ -58,13 +66,111 @@ impl Cube {
 
     // Wehther the cube contains the specific polarity of a variables
     pub fn contains_sign_var(&self, var: i32) -> bool {
-        return self.0.iter().any(|x| *x == var);
+        self.0.contains(&var)
     }
 
     // Whether the cube contains either polarity of a variable
     pub fn contains_var(&self, var: u32) -> bool {
-        return self.0.iter().any(|x| *x == pos_var(var) || *x == neg_var(var));
+        self.0.iter().any(|x| *x == pos_var(var) || *x == neg_var(var))
+    }
+
+    // The name of the files belonging to the cube, which is the same for every order of its
+    // literals. Long cubes are named `h` followed by the hash of their name, so arbitrarily
+    // deep cubes still fit in a file name.
+    pub fn file_name(&self) -> String {
+        let name = self.canonical().to_string();
+        if name.len() <= MAX_FILE_NAME_LEN {
+            return name;
+        }
+        format!("h{:016x}", fnv1a(name.as_bytes()))
+    }
+
+    // The literals sorted by variable, with the positive literal first, and without duplicates
+    pub fn canonical(&self) -> Cube {
+        let mut lits = self.0.clone();
+        lits.sort_by_key(|lit| (lit.unsigned_abs(), -lit.signum()));
+        lits.dedup();
+        Cube(lits)
+    }
+
+    // Whether the cube contains both polarities of some variable
+    pub fn is_contradictory(&self) -> bool {
+        let lits = self.canonical().0;
+        lits.windows(2).any(|w| w[0] == -w[1])
+    }
+
+    // Whether no assignment satisfies both cubes
+    pub fn conflicts(&self, other: &Cube) -> bool {
+        self.0.iter().any(|lit| other.0.contains(&-lit))
+    }
+}
+
+// Reads the cubes of an icnf or cube file, like the ones march_cu writes. Only the
+// `a ... 0` lines hold cubes, so the clauses of a full inccnf and comments are skipped.
+pub fn read_cube_file(path: &str) -> Result<Vec<Cube>, io::Error> {
+    let mut cubes = Vec::new();
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let Some(lits) = line.trim().strip_prefix("a ") else {
+            continue;
+        };
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{path}:{}: {msg}", i + 1));
+        let mut lits = lits
+            .split_whitespace()
+            .map(|lit| lit.parse::<i32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid("could not parse a literal of the cube"))?;
+        if lits.pop() != Some(0) || lits.contains(&0) {
+            return Err(invalid("a cube has to end with a single 0"));
+        }
+        cubes.push(Cube(lits));
+    }
+    Ok(cubes)
+}
+
+// Finds two cubes which an assignment can satisfy together, if there are any. Cubes on
+// opposite sides of a variable never overlap, so the cubes are split into the two sides of
+// their most common variable, with the cubes without it on both sides, until groups are small.
+pub fn find_overlap(cubes: &[Cube]) -> Option<(usize, usize)> {
+    fn pairwise(cubes: &[Cube], group: &[usize]) -> Option<(usize, usize)> {
+        group
+            .iter()
+            .tuple_combinations()
+            .find(|(i, j)| !cubes[**i].conflicts(&cubes[**j]))
+            .map(|(i, j)| (*i.min(j), *i.max(j)))
+    }
+
+    fn search(cubes: &[Cube], group: Vec<usize>, used: &mut HashSet<u32>) -> Option<(usize, usize)> {
+        if group.len() <= 16 {
+            return pairwise(cubes, &group);
+        }
+        let mut counts = HashMap::new();
+        for lit in group.iter().flat_map(|i| &cubes[*i].0) {
+            if !used.contains(&lit.unsigned_abs()) {
+                *counts.entry(lit.unsigned_abs()).or_insert(0) += 1;
+            }
+        }
+        let Some((var, _)) = counts.into_iter().max_by_key(|(var, count)| (*count, *var)) else {
+            return pairwise(cubes, &group);
+        };
+
+        let (mut pos, mut neg) = (Vec::new(), Vec::new());
+        for i in group {
+            match cubes[i].0.iter().find(|lit| lit.unsigned_abs() == var) {
+                Some(lit) if *lit > 0 => pos.push(i),
+                Some(_) => neg.push(i),
+                None => {
+                    pos.push(i);
+                    neg.push(i);
+                }
+            }
+        }
+        used.insert(var);
+        let overlap = search(cubes, pos, used).or_else(|| search(cubes, neg, used));
+        used.remove(&var);
+        overlap
     }
+
+    search(cubes, (0..cubes.len()).collect(), &mut HashSet::new())
 }
 
 pub fn pos_var(var: u32) -> i32 {
@@ -77,7 +183,8 @@ pub fn neg_var(var: u32) -> i32 {
 
 #[cfg(test)]
 mod tests {
-    use crate::cube::Cube;
+    use super::*;
+    use crate::test_utils::test_dir;
     use rand::Rng;
 
     #[test]
@@ -131,4 +238,72 @@ mod tests {
         assert!(!Cube(vec![-1, 2]).subsumes(&Cube(vec![1, 2])));
         assert!(!Cube(vec![1, 2]).subsumes(&Cube(vec![-1, 2])));
     }
+
+    #[test]
+    fn canonical_cubes() {
+        let cube = Cube(vec![3, -1, 2, 3]);
+        assert_eq!(cube.canonical(), Cube(vec![-1, 2, 3]));
+        assert_eq!(cube.canonical(), Cube(vec![2, -1, 3]).canonical());
+        assert_ne!(cube, Cube(vec![2, -1, 3]));
+        assert_eq!(Cube(vec![-1, 1]).canonical(), Cube(vec![1, -1]));
+    }
+
+    #[test]
+    fn contradictions_and_conflicts() {
+        assert!(Cube(vec![2, 1, -2]).is_contradictory());
+        assert!(!Cube(vec![2, 1, 3]).is_contradictory());
+        assert!(!Cube(vec![]).is_contradictory());
+
+        let a = Cube(vec![1, -2]);
+        let b = Cube(vec![3, 1]);
+        assert!(!a.conflicts(&b));
+        assert!(a.conflicts(&Cube(vec![2])));
+    }
+
+    #[test]
+    fn long_file_names_are_hashed() {
+        let short = Cube(vec![-120, -5, 34]);
+        assert_eq!(short.file_name(), "n5_34_n120");
+        assert_eq!(short.file_name(), Cube(vec![34, -120, -5]).file_name());
+
+        let deep = Cube((100000..100050).map(|v| -v).collect());
+        let name = deep.file_name();
+        assert!(name.starts_with('h') && name.len() == 17);
+        assert_ne!(name, Cube((100000..100051).map(|v| -v).collect()).file_name());
+    }
+
+    #[test]
+    fn reads_march_and_inccnf_cubes() {
+        let dir = test_dir("cube_file");
+        let path = dir.join("cubes.icnf");
+        fs::write(&path, "p inccnf\n1 2 0\nc metric: 1.0\na 1 -2 0\na  -1 0\n\na 0\n").unwrap();
+        let cubes = read_cube_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(cubes, vec![Cube(vec![1, -2]), Cube(vec![-1]), Cube(vec![])]);
+
+        for bad in ["a 1 2\n", "a 1 x 0\n", "a 1 0 2 0\n"] {
+            fs::write(&path, bad).unwrap();
+            assert!(
+                read_cube_file(path.to_str().unwrap()).is_err(),
+                "{bad:?} should not parse"
+            );
+        }
+    }
+
+    #[test]
+    fn overlapping_cubes() {
+        let cube = |s: &str| s.parse::<Cube>().unwrap();
+        let leaves = ["1_2", "1_n2", "n1_3", "n1_n3_4", "n1_n3_n4"].map(cube);
+        assert_eq!(find_overlap(&leaves), None);
+        assert_eq!(find_overlap(&[cube("1"), cube("n1_2"), cube("n1_3")]), Some((1, 2)));
+        assert_eq!(find_overlap(&[cube("1_2"), cube("2_1")]), Some((0, 1)));
+
+        // the leaves of a full tree of depth 8, which only overlap once one is added twice
+        let mut leaves = (0..256u32)
+            .map(|n| Cube((0..8).map(|v| if n >> v & 1 == 1 { v + 1 } else { -(v + 1) }).collect()))
+            .collect::<Vec<_>>();
+        assert_eq!(find_overlap(&leaves), None);
+        leaves.push(Cube(vec![-3, 5]));
+        let (i, j) = find_overlap(&leaves).unwrap();
+        assert!(j == 256 && !leaves[i].conflicts(&leaves[j]));
+    }
 }
@@ -0,0 +1,111 @@
+use crate::config::Config;
+use crate::cube::Cube;
+use crate::reconstruct::Leaf;
+use crate::runners::{eval_metric, remove_log, run_solver, SolverResult};
+use crate::shutdown;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::ThreadPool;
+
+// Runs the solver once on every cube with the configured timeout. The results go to
+// evaluation.log in the `cube: metric` form of best.log as they come in, with the outcome in
+// place of the metric for cubes which didn't finish, or `interrupted` when splits was
+// shutting down. A cube whose run couldn't be set up counts as failed.
+pub fn evaluate(config: &Config, pool: &ThreadPool, cubes: Vec<Cube>) -> Result<Vec<Leaf>, io::Error> {
+    let outfile = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(format!("{}/evaluation.log", config.output_dir))?;
+    let outfile = Mutex::new(outfile);
+
+    pool.install(|| {
+        cubes
+            .into_par_iter()
+            .map(|cube| {
+                let res = run_solver(config, &cube, config.timeout as f32);
+                let metric = record_result(config, &outfile, &cube, res)?;
+                remove_log(config, &cube)?;
+                // External cubes come without the tree they were split in
+                Ok(Leaf { cube, metric, depth: 0 })
+            })
+            .collect()
+    })
+}
+
+// Writes the line of one cube to evaluation.log and returns its metric if it finished
+fn record_result(
+    config: &Config,
+    outfile: &Mutex<File>,
+    cube: &Cube,
+    res: Result<SolverResult, io::Error>,
+) -> Result<Option<f32>, io::Error> {
+    let (line, metric) = match res {
+        Ok(SolverResult::Finished(metrics)) => {
+            let metric = eval_metric(config, &metrics);
+            (format!("{:?}", metric), Some(metric))
+        }
+        // The run may have been killed, or never started, because of the shutdown
+        Ok(SolverResult::Timeout) if shutdown::requested() => ("interrupted".to_string(), None),
+        Ok(SolverResult::Timeout) => ("timeout".to_string(), None),
+        Ok(SolverResult::MemoryOut) => ("memout".to_string(), None),
+        Ok(SolverResult::Failed(failure)) => (format!("failed, {}", failure), None),
+        Err(e) => {
+            println!("Could not run the solver on cube {cube}: {e}");
+            (format!("failed, {}", e), None)
+        }
+    };
+    // Whole lines are written at once, so lines of cubes finishing together don't mix
+    outfile
+        .lock()
+        .unwrap()
+        .write_all(format!("{}: {}\n", cube, line).as_bytes())?;
+    Ok(metric)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{test_config, CONSTANT_SOLVER};
+    use std::fs;
+
+    #[test]
+    fn evaluates_every_cube() {
+        let solver = concat!(
+            "#!/bin/sh\n",
+            "if grep -q '^-2 0' \"$1\"; then exit 3; fi\n",
+            "echo 'SPLITS DATA' > \"$2\"\necho '{\"time\": 1.5}' >> \"$2\"\n",
+        );
+        let config = test_config("evaluate", solver, "");
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let cubes = vec![Cube(vec![1, 2]), Cube(vec![1, -2]), Cube(vec![-1])];
+
+        let leaves = evaluate(&config, &pool, cubes).unwrap();
+        let metrics = leaves.iter().map(|leaf| leaf.metric).collect::<Vec<_>>();
+        assert_eq!(metrics, vec![Some(1.5), None, Some(1.5)]);
+        assert_eq!(
+            fs::read_to_string(format!("{}/evaluation.log", config.output_dir)).unwrap(),
+            "1_2: 1.5\n1_n2: failed, exited with code 3\nn1: 1.5\n"
+        );
+        assert_eq!(fs::read_dir(format!("{}/logs", config.output_dir)).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn cubes_which_cannot_run_fail_on_their_own() {
+        let config = test_config("evaluate_error", CONSTANT_SOLVER, "");
+        // the formula of cube 1 can't be written
+        fs::create_dir(format!("{}/1.cnf", config.tmp_dir)).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+
+        let leaves = evaluate(&config, &pool, vec![Cube(vec![1]), Cube(vec![-1])]).unwrap();
+        let metrics = leaves.iter().map(|leaf| leaf.metric).collect::<Vec<_>>();
+        assert_eq!(metrics, vec![None, Some(1.5)]);
+        let log = fs::read_to_string(format!("{}/evaluation.log", config.output_dir)).unwrap();
+        assert!(log.starts_with("1: failed, "), "{log}");
+        assert!(log.ends_with("\nn1: 1.5\n"), "{log}");
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+// An arithmetic expression over the metrics reported by the solver, e.g.
+// `time + 1e-6 * propagations` or `max(time, conflicts / 5000)`.
+// Metric names which aren't plain identifiers can be written in double quotes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    source: String,
+    root: Node,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Num(f32),
+    Metric(String),
+    Neg(Box<Node>),
+    BinOp(char, Box<Node>, Box<Node>),
+    Call(Func, Vec<Node>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Func {
+    Min,
+    Max,
+    Abs,
+    Sqrt,
+    Log,
+    Exp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f32),
+    Ident(String),
+    Quoted(String),
+    Op(char),
+    LParen,
+    RParen,
+    Comma,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError(pub String);
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Expr {
+    // Fails with the name of the first metric that isn't in `metrics`
+    pub fn eval(&self, metrics: &HashMap<String, f32>) -> Result<f32, String> {
+        self.root.eval(metrics)
+    }
+
+    // Whether the expression is just the metric `name`
+    pub fn is_metric(&self, name: &str) -> bool {
+        matches!(&self.root, Node::Metric(m) if m == name)
+    }
+
+    // The value of an expression which doesn't mention any metric
+    pub fn constant(&self) -> Option<f32> {
+        self.root.eval(&HashMap::new()).ok()
+    }
+}
+
+impl Node {
+    fn eval(&self, metrics: &HashMap<String, f32>) -> Result<f32, String> {
+        Ok(match self {
+            Node::Num(x) => *x,
+            Node::Metric(name) => *metrics.get(name).ok_or_else(|| name.clone())?,
+            Node::Neg(e) => -e.eval(metrics)?,
+            Node::BinOp(op, l, r) => {
+                let (l, r) = (l.eval(metrics)?, r.eval(metrics)?);
+                match op {
+                    '+' => l + r,
+                    '-' => l - r,
+                    '*' => l * r,
+                    '/' => l / r,
+                    '^' => l.powf(r),
+                    _ => unreachable!(),
+                }
+            }
+            Node::Call(func, args) => {
+                let args = args.iter().map(|a| a.eval(metrics)).collect::<Result<Vec<_>, _>>()?;
+                match func {
+                    Func::Min => args.into_iter().reduce(f32::min).unwrap(),
+                    Func::Max => args.into_iter().reduce(f32::max).unwrap(),
+                    Func::Abs => args[0].abs(),
+                    Func::Sqrt => args[0].sqrt(),
+                    Func::Log => args[0].ln(),
+                    Func::Exp => args[0].exp(),
+                }
+            }
+        })
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, ExprError> {
+    fn take_while(chars: &mut Peekable<Chars>, pred: impl Fn(char) -> bool) -> String {
+        let mut out = String::new();
+        while let Some(c) = chars.next_if(|c| pred(*c)) {
+            out.push(c);
+        }
+        out
+    }
+
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            '0'..='9' | '.' => {
+                let mut num = take_while(&mut chars, |c| c.is_ascii_digit() || c == '.');
+                // exponent, as in 1e-6
+                if let Some(e) = chars.next_if(|c| *c == 'e' || *c == 'E') {
+                    num.push(e);
+                    if let Some(sign) = chars.next_if(|c| *c == '+' || *c == '-') {
+                        num.push(sign);
+                    }
+                    num.push_str(&take_while(&mut chars, |c| c.is_ascii_digit()));
+                }
+                match num.parse() {
+                    Ok(x) => tokens.push(Token::Num(x)),
+                    Err(_) => return Err(ExprError(format!("Cannot parse {num} as a number"))),
+                }
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                tokens.push(Token::Ident(take_while(&mut chars, |c| {
+                    c.is_alphanumeric() || c == '_'
+                })));
+            }
+            '"' => {
+                chars.next();
+                let name = take_while(&mut chars, |c| c != '"');
+                if chars.next().is_none() {
+                    return Err(ExprError(format!("Unterminated metric name \"{name}")));
+                }
+                tokens.push(Token::Quoted(name));
+            }
+            '+' | '-' | '*' | '/' | '^' => {
+                chars.next();
+                tokens.push(Token::Op(c));
+            }
+            '(' | ')' | ',' => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    _ => Token::Comma,
+                });
+            }
+            _ => return Err(ExprError(format!("Unexpected character '{c}'"))),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), ExprError> {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            Some(t) => Err(ExprError(format!("Expected {token:?} but found {t:?}"))),
+            None => Err(ExprError(format!("Expected {token:?} but the expression ended"))),
+        }
+    }
+
+    // expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.term()?;
+        while let Some(Token::Op(op @ ('+' | '-'))) = self.peek().cloned() {
+            self.pos += 1;
+            node = Node::BinOp(op, Box::new(node), Box::new(self.term()?));
+        }
+        Ok(node)
+    }
+
+    // term := unary (('*' | '/') unary)*
+    fn term(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.unary()?;
+        while let Some(Token::Op(op @ ('*' | '/'))) = self.peek().cloned() {
+            self.pos += 1;
+            node = Node::BinOp(op, Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    // unary := '-' unary | atom ('^' unary)?
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        if let Some(Token::Op('-')) = self.peek() {
+            self.pos += 1;
+            return Ok(Node::Neg(Box::new(self.unary()?)));
+        }
+        let base = self.atom()?;
+        if let Some(Token::Op('^')) = self.peek() {
+            self.pos += 1;
+            return Ok(Node::BinOp('^', Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    // atom := number | metric | function '(' expr (',' expr)* ')' | '(' expr ')'
+    fn atom(&mut self) -> Result<Node, ExprError> {
+        match self.next() {
+            Some(Token::Num(x)) => Ok(Node::Num(x)),
+            Some(Token::Quoted(name)) => Ok(Node::Metric(name)),
+            Some(Token::Ident(name)) => {
+                if self.peek() != Some(&Token::LParen) {
+                    return Ok(Node::Metric(name));
+                }
+                self.pos += 1;
+                let (func, arity) = match name.as_str() {
+                    "min" => (Func::Min, None),
+                    "max" => (Func::Max, None),
+                    "abs" => (Func::Abs, Some(1)),
+                    "sqrt" => (Func::Sqrt, Some(1)),
+                    "log" => (Func::Log, Some(1)),
+                    "exp" => (Func::Exp, Some(1)),
+                    _ => return Err(ExprError(format!("Unknown function {name}"))),
+                };
+                let mut args = vec![self.expr()?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.pos += 1;
+                    args.push(self.expr()?);
+                }
+                self.expect(Token::RParen)?;
+                if arity.is_some_and(|n| n != args.len()) {
+                    return Err(ExprError(format!("{name} takes exactly one argument")));
+                }
+                Ok(Node::Call(func, args))
+            }
+            Some(Token::LParen) => {
+                let node = self.expr()?;
+                self.expect(Token::RParen)?;
+                Ok(node)
+            }
+            Some(t) => Err(ExprError(format!("Unexpected {t:?}"))),
+            None => Err(ExprError("The expression ended unexpectedly".to_string())),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ExprError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(s)?, pos: 0 };
+        let root = parser.expr()?;
+        if let Some(t) = parser.peek() {
+            return Err(ExprError(format!("Unexpected {t:?} after the expression")));
+        }
+        Ok(Expr { source: s.trim().to_string(), root })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics() -> HashMap<String, f32> {
+        HashMap::from([
+            ("time".to_string(), 2.0),
+            ("conflicts".to_string(), 20000.0),
+            ("propagations".to_string(), 1e6),
+            ("learned clauses".to_string(), 8.0),
+        ])
+    }
+
+    fn eval(s: &str) -> f32 {
+        s.parse::<Expr>().unwrap().eval(&metrics()).unwrap()
+    }
+
+    #[test]
+    fn single_metric() {
+        assert_eq!(eval("time"), 2.0);
+        assert_eq!(eval("  conflicts "), 20000.0);
+    }
+
+    #[test]
+    fn arithmetic_and_precedence() {
+        assert_eq!(eval("time + 1e-6 * propagations"), 3.0);
+        assert_eq!(eval("(time + 1) * 2"), 6.0);
+        assert_eq!(eval("time - 1 - 1"), 0.0);
+        assert_eq!(eval("-time ^ 2"), -4.0);
+        assert_eq!(eval("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(eval("conflicts / 5000 / 2"), 2.0);
+    }
+
+    #[test]
+    fn functions_and_quoted_metrics() {
+        assert_eq!(eval("max(time, conflicts/5000)"), 4.0);
+        assert_eq!(eval("min(time, 7, \"learned clauses\")"), 2.0);
+        assert_eq!(eval("sqrt(abs(-\"learned clauses\" * 2))"), 4.0);
+    }
+
+    #[test]
+    fn missing_metric() {
+        let e = "time + ticks".parse::<Expr>().unwrap();
+        assert_eq!(e.eval(&metrics()), Err("ticks".to_string()));
+        assert_eq!(e.constant(), None);
+        assert_eq!("1.5 * 2".parse::<Expr>().unwrap().constant(), Some(3.0));
+        assert!(" time ".parse::<Expr>().unwrap().is_metric("time"));
+        assert!(!"time + 0".parse::<Expr>().unwrap().is_metric("time"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for bad in [
+            "",
+            "time +",
+            "max(time",
+            "foo(time)",
+            "abs(1, 2)",
+            "time time",
+            "\"time",
+            "time % 2",
+            "1e",
+        ] {
+            assert!(bad.parse::<Expr>().is_err(), "{bad} should not parse");
+        }
+    }
+
+    #[test]
+    fn display_keeps_source() {
+        assert_eq!(
+            "max(time,  conflicts)".parse::<Expr>().unwrap().to_string(),
+            "max(time,  conflicts)"
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use crate::config::SatType;
+use crate::cube::Cube;
+use crate::reconstruct::{write_cubes_after, Leaf};
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// How often the leaves are written at most while the tree grows
+pub const WRITE_INTERVAL: Duration = Duration::from_secs(1);
+
+// The metric and depth of every leaf, by its literals
+type Leaves = HashMap<Vec<i32>, (Option<f32>, usize)>;
+
+// The leaves of the tree built so far, with the metric each was measured at and how many
+// splits led to it. Roots haven't been measured, so they have no metric.
+pub struct Frontier {
+    state: Mutex<State>,
+    output: Option<Output>,
+}
+
+struct State {
+    leaves: Leaves,
+    // Counts the changes to the leaves, so an older snapshot never replaces a newer one
+    generation: u64,
+    last_snapshot: Option<Instant>,
+}
+
+// A copy of the leaves taken after a change, to be written without holding the lock
+type Snapshot = (u64, Vec<Leaf>);
+
+// Where the leaves are written, with the formula for a full inccnf. The formula is
+// rendered once, and `written` is the generation of the leaves in the file.
+struct Output {
+    loc: String,
+    formula: Option<String>,
+    interval: Duration,
+    written: Mutex<Option<u64>>,
+}
+
+impl Frontier {
+    fn from_leaves(leaves: Leaves) -> Frontier {
+        Frontier {
+            state: Mutex::new(State { leaves, generation: 0, last_snapshot: None }),
+            output: None,
+        }
+    }
+
+    pub fn new(roots: &[Cube]) -> Frontier {
+        Frontier::from_leaves(roots.iter().map(|root| (root.0.clone(), (None, 0))).collect())
+    }
+
+    // Starts from the leaves of an earlier run, which keep the metric they were measured at
+    pub fn with_leaves(leaves: &[Leaf]) -> Frontier {
+        Frontier::from_leaves(
+            leaves
+                .iter()
+                .map(|leaf| (leaf.cube.0.clone(), (leaf.metric, leaf.depth)))
+                .collect(),
+        )
+    }
+
+    // Keeps `loc` up to date with the leaves, so a killed run still leaves a partition of
+    // the search space behind. The leaves are written right away, and then after a split
+    // unless the last write was less than `interval` ago. Splits which come too soon are
+    // left to the next write, so the caller writes the final leaves itself.
+    pub fn write_to(
+        mut self,
+        loc: String,
+        formula: Option<Arc<SatType>>,
+        interval: Duration,
+    ) -> Result<Frontier, io::Error> {
+        let formula = match formula.as_deref() {
+            Some(SatType::Cnf(cnf)) => Some(cnf.to_inccnf_string()),
+            _ => None,
+        };
+        self.output = Some(Output { loc, formula, interval, written: Mutex::new(None) });
+        let snapshot = self.changed(&mut self.state.lock().unwrap());
+        self.write(snapshot)?;
+        Ok(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().leaves.len()
+    }
+
+    // Called with the lock held after every change. Returns the leaves to write, if they
+    // are written at all and the last write was long enough ago.
+    fn changed(&self, state: &mut State) -> Option<Snapshot> {
+        state.generation += 1;
+        let output = self.output.as_ref()?;
+        if state.last_snapshot.is_some_and(|last| last.elapsed() < output.interval) {
+            return None;
+        }
+        state.last_snapshot = Some(Instant::now());
+        let leaves = state
+            .leaves
+            .iter()
+            .map(|(cube, (metric, depth))| Leaf { cube: Cube(cube.clone()), metric: *metric, depth: *depth })
+            .collect();
+        Some((state.generation, leaves))
+    }
+
+    // Called without the lock, so only concurrent writes wait for each other
+    fn write(&self, snapshot: Option<Snapshot>) -> Result<(), io::Error> {
+        let (Some(output), Some((generation, mut leaves))) = (&self.output, snapshot) else {
+            return Ok(());
+        };
+        let mut written = output.written.lock().unwrap();
+        if written.is_some_and(|written| written >= generation) {
+            return Ok(());
+        }
+        leaves.sort_by(|x, y| x.cube.cmp(&y.cube));
+        write_cubes_after(&leaves, &output.loc, output.formula.as_deref())?;
+        *written = Some(generation);
+        Ok(())
+    }
+
+    // Replaces `parent` by the cubes of its chosen split
+    pub fn split(&self, parent: &Cube, children: &[(Cube, f32)]) -> Result<(), io::Error> {
+        let snapshot = {
+            let mut state = self.state.lock().unwrap();
+            let depth = state.leaves.remove(&parent.0).map_or(0, |(_, depth)| depth) + 1;
+            state.leaves.extend(
+                children
+                    .iter()
+                    .map(|(cube, metric)| (cube.0.clone(), (Some(*metric), depth))),
+            );
+            self.changed(&mut state)
+        };
+        self.write(snapshot)
+    }
+
+    // Picks the class giving the smallest makespan on `workers` workers and splits `parent`
+    // into it, as long as that beats leaving `parent` as a leaf. When several leaves are
+    // equally hard the makespan can only drop once all of them are split, so a split which
+    // keeps the makespan but shortens the longest job of `parent` also counts.
+    // Unmeasured leaves are left out of the estimate, and splitting them is always taken.
+    pub fn split_if_faster(
+        &self,
+        parent: &Cube,
+        classes: &[Vec<(Cube, f32)>],
+        workers: usize,
+    ) -> Result<Option<usize>, io::Error> {
+        let mut state = self.state.lock().unwrap();
+        let (parent_metric, parent_depth) = state.leaves.remove(&parent.0).unwrap_or((None, 0));
+        let others = state
+            .leaves
+            .values()
+            .filter_map(|(metric, _)| *metric)
+            .collect::<Vec<_>>();
+        let makespan_with =
+            |metrics: &mut dyn Iterator<Item = f32>| lpt_makespan(others.iter().copied().chain(metrics), workers);
+
+        let best = classes
+            .iter()
+            .map(|class| makespan_with(&mut class.iter().map(|(_, metric)| *metric)))
+            .enumerate()
+            .min_by(|(_, x), (_, y)| x.total_cmp(y));
+        let faster = match (best, parent_metric) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some((i, after)), Some(metric)) => {
+                let before = makespan_with(&mut [metric].into_iter());
+                let longest = classes[i].iter().map(|(_, m)| *m).fold(f32::MIN, f32::max);
+                after < before || (after == before && longest < metric)
+            }
+        };
+
+        match best {
+            Some((i, _)) if faster => {
+                state.leaves.extend(
+                    classes[i]
+                        .iter()
+                        .map(|(cube, metric)| (cube.0.clone(), (Some(*metric), parent_depth + 1))),
+                );
+                let snapshot = self.changed(&mut state);
+                drop(state);
+                self.write(snapshot)?;
+                Ok(Some(i))
+            }
+            _ => {
+                state.leaves.insert(parent.0.clone(), (parent_metric, parent_depth));
+                Ok(None)
+            }
+        }
+    }
+}
+
+// The time it takes `workers` workers to get through jobs taking `times`, when every job
+// goes, longest first, to the worker which is free soonest.
+pub fn lpt_makespan(times: impl Iterator<Item = f32>, workers: usize) -> f32 {
+    let mut times = times.collect::<Vec<_>>();
+    times.sort_by(|x, y| y.total_cmp(x));
+
+    // loads are kept as bits so the heap can order them, which works for non-negative floats
+    let mut loads = BinaryHeap::from(vec![Reverse(0u32); workers.max(1)]);
+    for time in times {
+        let Reverse(load) = loads.pop().unwrap();
+        loads.push(Reverse((f32::from_bits(load) + time.max(0.0)).to_bits()));
+    }
+    loads
+        .into_iter()
+        .map(|Reverse(load)| f32::from_bits(load))
+        .fold(0.0, f32::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube(s: &str) -> Cube {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn lpt_schedule() {
+        assert_eq!(lpt_makespan([3.0, 3.0, 2.0, 2.0, 2.0].into_iter(), 2), 7.0);
+        assert_eq!(lpt_makespan([5.0, 1.0, 1.0].into_iter(), 4), 5.0);
+        assert_eq!(lpt_makespan([1.0, 2.0, 3.0].into_iter(), 1), 6.0);
+        assert_eq!(lpt_makespan(std::iter::empty(), 3), 0.0);
+    }
+
+    #[test]
+    fn splits_only_while_faster() {
+        let frontier = Frontier::new(&[cube("")]);
+        let classes = vec![
+            vec![(cube("1"), 4.0), (cube("n1"), 4.0)],
+            vec![(cube("2"), 1.0), (cube("n2"), 6.0)],
+        ];
+        // the root hasn't been measured, so any split is taken
+        assert_eq!(frontier.split_if_faster(&cube(""), &classes, 2).unwrap(), Some(0));
+
+        // 1 and n1 take 4.0 each, so on 2 workers splitting either into 3.0 and 3.0 is slower
+        let classes = vec![vec![(cube("1_3"), 3.0), (cube("1_n3"), 3.0)]];
+        assert_eq!(frontier.split_if_faster(&cube("1"), &classes, 2).unwrap(), None);
+        // on 3 workers the makespan stays at 4.0 until n1 is split as well
+        assert_eq!(frontier.split_if_faster(&cube("1"), &classes, 3).unwrap(), Some(0));
+        let classes = vec![vec![(cube("n1_4"), 3.5), (cube("n1_n4"), 3.5)]];
+        assert_eq!(frontier.split_if_faster(&cube("n1"), &classes, 3).unwrap(), None);
+        assert_eq!(frontier.split_if_faster(&cube("n1"), &classes, 4).unwrap(), Some(0));
+
+        // splitting without shortening the longest job never helps
+        let classes = vec![vec![(cube("1_3_5"), 3.0), (cube("1_3_n5"), 0.5)]];
+        assert_eq!(frontier.split_if_faster(&cube("1_3"), &classes, 8).unwrap(), None);
+    }
+
+    #[test]
+    fn leaves_are_written_after_every_split() {
+        let loc = crate::test_utils::test_dir("anytime").join("cubes.icnf");
+        let loc = loc.to_str().unwrap();
+        let frontier = Frontier::new(&[cube("")])
+            .write_to(loc.to_string(), None, Duration::ZERO)
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(loc).unwrap(), "a 0\n");
+
+        frontier
+            .split(&cube(""), &[(cube("1"), 2.0), (cube("n1"), 1.0)])
+            .unwrap();
+        frontier
+            .split(&cube("n1"), &[(cube("n1_2"), 0.5), (cube("n1_n2"), 0.5)])
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(loc).unwrap(), "a -1 -2 0\na -1 2 0\na 1 0\n");
+        assert!(!std::path::Path::new(&format!("{loc}.tmp")).exists());
+
+        let formula = Arc::new(SatType::Cnf("p cnf 2 1\n1 2 0\n".parse().unwrap()));
+        let frontier = Frontier::new(&[cube("")])
+            .write_to(loc.to_string(), Some(formula), Duration::ZERO)
+            .unwrap();
+        frontier
+            .split(&cube(""), &[(cube("1"), 2.0), (cube("n1"), 1.0)])
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(loc).unwrap(),
+            "p inccnf\n1 2 0\nc metric: 1.0\nc depth: 1\na -1 0\nc metric: 2.0\nc depth: 1\na 1 0\n"
+        );
+    }
+
+    #[test]
+    fn writes_are_throttled() {
+        let loc = crate::test_utils::test_dir("anytime_throttled").join("cubes.icnf");
+        let loc = loc.to_str().unwrap();
+        let frontier = Frontier::new(&[cube("")])
+            .write_to(loc.to_string(), None, Duration::from_secs(3600))
+            .unwrap();
+        frontier
+            .split(&cube(""), &[(cube("1"), 2.0), (cube("n1"), 1.0)])
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(loc).unwrap(), "a 0\n");
+
+        // an older snapshot never replaces a newer one
+        let (first, second) = {
+            let mut state = frontier.state.lock().unwrap();
+            state.last_snapshot = None;
+            let first = frontier.changed(&mut state);
+            state.leaves.remove(&cube("1").0);
+            state.last_snapshot = None;
+            (first, frontier.changed(&mut state))
+        };
+        frontier.write(second).unwrap();
+        frontier.write(first).unwrap();
+        assert_eq!(std::fs::read_to_string(loc).unwrap(), "a -1 0\n");
+    }
+}
@@ -0,0 +1,69 @@
+use crate::config::Config;
+
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+use nix::sched::{sched_getaffinity, sched_setaffinity, CpuSet};
+use nix::sys::resource::{setrlimit, Resource};
+use nix::unistd::Pid;
+
+static FREE_CORES: OnceLock<Mutex<Vec<usize>>> = OnceLock::new();
+
+// The cores this process is allowed to run on
+pub fn usable_cores() -> Vec<usize> {
+    match sched_getaffinity(Pid::from_raw(0)) {
+        Ok(set) => (0..CpuSet::count())
+            .filter(|core| set.is_set(*core).unwrap_or(false))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// A core reserved for a single solver run, which is handed back when dropped
+pub struct PinnedCore(usize);
+
+impl PinnedCore {
+    fn take() -> Option<PinnedCore> {
+        FREE_CORES
+            .get_or_init(|| Mutex::new(usable_cores()))
+            .lock()
+            .unwrap()
+            .pop()
+            .map(PinnedCore)
+    }
+}
+
+impl Drop for PinnedCore {
+    fn drop(&mut self) {
+        FREE_CORES.get().unwrap().lock().unwrap().push(self.0);
+    }
+}
+
+// Applies the memory limit and core pinning of the config to the solver spawned by `command`.
+// The returned core must be held until the solver has exited.
+pub fn limit_solver(config: &Config, command: &mut Command) -> Option<PinnedCore> {
+    let pinned_core = if config.pin_cores { PinnedCore::take() } else { None };
+    let core = pinned_core.as_ref().map(|c| c.0);
+    let memory_limit = config.memory_limit.map(|mb| mb * 1024 * 1024);
+
+    if memory_limit.is_some() || core.is_some() {
+        // Safety: only setrlimit and sched_setaffinity run between fork and exec,
+        // and both are plain system calls.
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(bytes) = memory_limit {
+                    setrlimit(Resource::RLIMIT_AS, bytes, bytes)?;
+                }
+                if let Some(core) = core {
+                    let mut cpu_set = CpuSet::new();
+                    cpu_set.set(core)?;
+                    sched_setaffinity(Pid::from_raw(0), &cpu_set)?;
+                }
+                Ok(())
+            });
+        }
+    }
+
+    pinned_core
+}
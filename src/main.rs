@@ -1,10 +1,22 @@
+mod budget;
+mod cache;
 mod clause;
 mod cmd_line;
 mod cnf;
+mod comparator;
 mod config;
 mod cube;
+mod evaluate;
+mod expr;
+mod frontier;
+mod limits;
 mod reconstruct;
+mod refine;
+mod remote;
 mod runners;
+mod shutdown;
+#[cfg(test)]
+mod test_utils;
 mod wcnf;
 
 use std::io::{stdin, stdout, Write};
@@ -12,18 +24,27 @@ use std::path::Path;
 use std::process::exit;
 use std::{fs, io};
 
-use cmd_line::get_args;
-use config::{Config, ConfigError};
-use cube::Cube;
-use reconstruct::parse_logs;
-use runners::{hyper_vec, preprocess, tree_gen};
+use cache::{install_cache, Cache};
+use cmd_line::{get_args, Mode};
+use config::{Config, ConfigError, SatType};
+use cube::{read_cube_file, Cube};
+use evaluate::evaluate;
+use frontier::Frontier;
+use rayon::ThreadPool;
+use reconstruct::{leaf_report, parse_logs, parse_multitree_logs, write_cubes};
+use refine::{hard_leaves, read_run_leaves, refined_leaves};
+use remote::{install_dispatcher, run_worker, Dispatcher};
+use runners::{hyper_vec, index_file_name, preprocess, tree_gen, tree_gen_roots};
 
 fn setup_directories(config: &Config) -> Result<(), io::Error> {
     if !Path::exists(Path::new(&config.output_dir)) {
         fs::create_dir(&config.output_dir)?;
     }
 
-    if config.multitree_variables.is_none() && !Path::exists(Path::new(&format!("{}/logs", &config.output_dir))) {
+    if config.multitree_variables.is_none()
+        && config.starting_cubes.is_none()
+        && !Path::exists(Path::new(&format!("{}/logs", &config.output_dir)))
+    {
         fs::create_dir(format!("{}/logs", &config.output_dir))?;
     }
 
@@ -34,6 +55,31 @@ fn setup_directories(config: &Config) -> Result<(), io::Error> {
     Ok(())
 }
 
+// Grows a tree under each root in its own subdirectory of the output directory. Each root
+// starts from its metric, which is the worst score for roots that were never measured.
+fn grow_subtrees(
+    config: &Config,
+    pool: &ThreadPool,
+    frontier: &Frontier,
+    roots: &[(Cube, f32)],
+) -> Result<(), io::Error> {
+    let mut subtrees = Vec::with_capacity(roots.len());
+    for (root, metric) in roots.iter().cloned() {
+        let mut subtree_config = config.clone();
+        subtree_config.output_dir = format!("{}/{}", config.output_dir, root.file_name());
+        index_file_name(&config.output_dir, &root)?;
+        fs::create_dir(&subtree_config.output_dir)?;
+        fs::create_dir(format!("{}/logs", &subtree_config.output_dir))?;
+        subtrees.push((subtree_config, root, metric));
+    }
+
+    let roots = subtrees
+        .iter()
+        .map(|(subtree_config, root, metric)| (subtree_config, root.clone(), *metric))
+        .collect();
+    tree_gen_roots(pool, frontier, roots, config.timeout as f32)
+}
+
 fn main() -> Result<(), io::Error> {
     let args = get_args();
     let config_string = match fs::read_to_string(args.config_file) {
@@ -52,6 +98,27 @@ fn main() -> Result<(), io::Error> {
         }
     };
 
+    install_cache(match &config.cache_dir {
+        Some(dir) => Cache::open(dir, &config)?,
+        None => Cache::in_memory(),
+    });
+
+    if let Some(Mode::Worker { connect }) = &args.mode {
+        shutdown::install_handler()?;
+        fs::create_dir_all(format!("{}/logs", &config.output_dir))?;
+        fs::create_dir_all(&config.tmp_dir)?;
+        // The temporary directory is left in place since several workers may share it.
+        run_worker(&config, connect)?;
+        return Ok(());
+    }
+
+    if let Some(Mode::Refine { run, .. }) = &args.mode {
+        if Path::new(&config.output_dir).canonicalize().ok() == Some(Path::new(run).canonicalize()?) {
+            println!("The output dir of the config has to be another directory than the run which is refined.");
+            exit(1);
+        }
+    }
+
     if !args.no_confirm {
         println!("Configuration:");
         println!("{}\n", config);
@@ -67,6 +134,9 @@ fn main() -> Result<(), io::Error> {
         }
     }
 
+    shutdown::install_handler()?;
+    budget::start_clock(&config);
+
     let pool = match rayon::ThreadPoolBuilder::new().num_threads(config.thread_count).build() {
         Ok(p) => p,
         Err(_) => {
@@ -77,41 +147,111 @@ fn main() -> Result<(), io::Error> {
 
     setup_directories(&config)?;
 
-    let start_cutoff = match config.comparator {
-        config::Comparator::MaxOfMin => f32::MIN,
-        config::Comparator::MinOfMax => f32::MAX,
-    };
+    if let Some(Mode::Coordinator { listen }) = &args.mode {
+        let dispatcher = Dispatcher::listen(listen)?;
+        println!("Waiting for workers on {}", dispatcher.local_addr());
+        install_dispatcher(dispatcher);
+    }
+
+    if let Some(Mode::Evaluate { cubes }) = &args.mode {
+        let cubes = read_cube_file(cubes)?;
+        fs::create_dir_all(format!("{}/logs", &config.output_dir))?;
+        let leaves = evaluate(&config, &pool, cubes)?;
+        println!("{}", leaf_report(&config.comparator, config.conquer_workers, &leaves));
+        if !config.preserve_cnf {
+            fs::remove_dir_all(config.tmp_dir)?;
+        }
+        if let Some(reason) = shutdown::stop_reason() {
+            println!("Stopped because {reason}: evaluation.log holds the cubes which were run.");
+        } else if shutdown::requested() {
+            println!("Interrupted: evaluation.log holds the cubes which were run.");
+            exit(130);
+        }
+        return Ok(());
+    }
+
+    let start_cutoff = config.comparator.worst();
     if config.preproc_count.is_some() {
+        // Runs with several roots only create the logs directories of their subtrees
+        fs::create_dir_all(format!("{}/logs", &config.output_dir))?;
         config.variables = preprocess(&config, &pool)?;
         if config.debug {
             println!("Set of new variables: {:?}", config.variables);
         }
     }
 
-    match config.multitree_variables.to_owned() {
-        Some(mut multitree_vars) => {
-            let hvs = hyper_vec(&mut multitree_vars);
-            let original_output_dir = config.output_dir;
-            for v in hvs {
-                let starter_cube = Cube(v);
-                config.output_dir = format!("{}/{}", original_output_dir, &starter_cube);
-                fs::create_dir(&config.output_dir)?;
-                fs::create_dir(format!("{}/logs", &config.output_dir))?;
-                tree_gen(&config, &pool, &starter_cube, start_cutoff, config.timeout as f32)?;
-            }
+    // Cubes are written on their own unless the whole formula was asked for
+    let base = match &*config.cnf {
+        SatType::Cnf(cnf) if config.full_inccnf => Some(cnf),
+        _ => None,
+    };
+    // Multitree variables are a shorthand for the starting cubes of every assignment to them
+    let starter_cubes = match (config.multitree_variables.to_owned(), &config.starting_cubes) {
+        (Some(mut multitree_vars), _) => Some(hyper_vec(&mut multitree_vars).into_iter().map(Cube).collect::<Vec<_>>()),
+        (None, starting_cubes) => starting_cubes.clone(),
+    };
+    // The frontier keeps cubes.icnf current while the tree grows, and it is written from the
+    // logs once more at the end, in the order of the tree and with coarsening
+    let cubes_loc = format!("{}/cubes.icnf", config.output_dir);
+    let formula = config.full_inccnf.then(|| config.cnf.clone());
+    let leaves = match (&args.mode, starter_cubes) {
+        (Some(Mode::Refine { run, threshold }), _) => {
+            let leaves = read_run_leaves(run)?;
+            let hard = hard_leaves(&config.comparator, &leaves, *threshold);
+            println!("Refining {} of the {} leaves of {}", hard.len(), leaves.len(), run);
+
+            let frontier =
+                Frontier::with_leaves(&leaves).write_to(cubes_loc.clone(), formula, frontier::WRITE_INTERVAL)?;
+            grow_subtrees(&config, &pool, &frontier, &hard)?;
+            let hard_cubes = hard.into_iter().map(|(cube, _)| cube).collect::<Vec<_>>();
+            let leaves = refined_leaves(&config.output_dir, leaves, &hard_cubes, config.coarsen_threshold)?;
+            write_cubes(&leaves, &cubes_loc, base)?;
+            leaves
+        }
+        (_, Some(starter_cubes)) => {
+            let frontier =
+                Frontier::new(&starter_cubes).write_to(cubes_loc.clone(), formula, frontier::WRITE_INTERVAL)?;
+            let roots = starter_cubes
+                .iter()
+                .map(|cube| (cube.clone(), start_cutoff))
+                .collect::<Vec<_>>();
+            grow_subtrees(&config, &pool, &frontier, &roots)?;
+            parse_multitree_logs(
+                &config.output_dir,
+                &starter_cubes,
+                &cubes_loc,
+                base,
+                config.coarsen_threshold,
+            )?
         }
-        None => {
-            tree_gen(&config, &pool, &Cube(Vec::new()), start_cutoff, config.timeout as f32)?;
+        (_, None) => {
+            let root = Cube(Vec::new());
+            let frontier = Frontier::new(std::slice::from_ref(&root)).write_to(
+                cubes_loc.clone(),
+                formula,
+                frontier::WRITE_INTERVAL,
+            )?;
+            tree_gen(&config, &pool, &frontier, &root, start_cutoff, config.timeout as f32)?;
             parse_logs(
                 &format!("{}/best.log", config.output_dir),
-                &format!("{}/cubes.icnf", config.output_dir),
-            )?;
+                &cubes_loc,
+                base,
+                config.coarsen_threshold,
+            )?
         }
     };
+    println!("{}", leaf_report(&config.comparator, config.conquer_workers, &leaves));
 
     if !config.preserve_cnf {
         fs::remove_dir_all(config.tmp_dir)?;
     }
 
+    if let Some(reason) = shutdown::stop_reason() {
+        println!("Stopped because {reason}: cubes.icnf holds the tree built so far, with unexpanded nodes as leaves.");
+    } else if shutdown::requested() {
+        println!("Interrupted: cubes.icnf holds the tree built so far, with unexpanded nodes as leaves.");
+        exit(130);
+    }
+
     Ok(())
 }
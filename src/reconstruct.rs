@@ -1,9 +1,27 @@
-use std::fs::{File, OpenOptions};
-use std::io::{self, BufRead, BufReader, Write};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
 
+use itertools::Itertools;
+
+use crate::cnf::Cnf;
+use crate::comparator::Comparator;
 use crate::cube::Cube;
+use crate::frontier::lpt_makespan;
+
+// A cube of the finished tree which was never split
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leaf {
+    pub cube: Cube,
+    // The metric best.log recorded for the cube, which roots don't have
+    pub metric: Option<f32>,
+    // How many splits led to the cube
+    pub depth: usize,
+}
 
-fn parse_leaf_cubes(log_loc: &str) -> Result<Vec<Cube>, io::Error> {
+// The cubes recorded in a best.log with their metrics, in the order they were written
+pub fn read_logged_cubes(log_loc: &str) -> Result<Vec<(Cube, Option<f32>)>, io::Error> {
     let log_file = File::open(log_loc)?;
     let log_content = BufReader::new(log_file);
     let mut cubes = Vec::new();
@@ -12,37 +30,438 @@ fn parse_leaf_cubes(log_loc: &str) -> Result<Vec<Cube>, io::Error> {
         if line.trim().is_empty() {
             break;
         }
-        let cube_str = line.split(':').next().unwrap();
+        let (cube_str, metric_str) = line.split_once(':').unwrap_or((&line, ""));
         let cube: Cube = cube_str.parse().unwrap();
-        cubes.push(cube);
+        cubes.push((cube, metric_str.trim().parse().ok()));
+    }
+    Ok(cubes)
+}
+
+// The recorded cubes by their literals in order. Every cube in a best.log extends the
+// cube it was split from, so the children of a cube are below it in the trie.
+pub struct CubeTrie {
+    nodes: Vec<TrieNode>,
+}
+
+#[derive(Default)]
+struct TrieNode {
+    children: HashMap<i32, usize>,
+    recorded: bool,
+}
+
+impl CubeTrie {
+    pub fn new(cubes: &[Cube]) -> CubeTrie {
+        let mut trie = CubeTrie { nodes: vec![TrieNode::default()] };
+        for cube in cubes {
+            let node = trie.insert(cube);
+            trie.nodes[node].recorded = true;
+        }
+        trie
+    }
+
+    fn insert(&mut self, cube: &Cube) -> usize {
+        let mut node = 0;
+        for lit in &cube.0 {
+            node = match self.nodes[node].children.get(lit) {
+                Some(child) => *child,
+                None => {
+                    self.nodes.push(TrieNode::default());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children.insert(*lit, child);
+                    child
+                }
+            };
+        }
+        node
+    }
+
+    fn find(&self, cube: &Cube) -> Option<usize> {
+        cube.0
+            .iter()
+            .try_fold(0, |node, lit| self.nodes[node].children.get(lit).copied())
+    }
+
+    // Whether no recorded cube extends `cube`
+    pub fn is_leaf(&self, cube: &Cube) -> bool {
+        self.find(cube).is_none_or(|node| self.nodes[node].children.is_empty())
     }
 
-    let mut leaf_cubes = Vec::new();
-    for cube in &cubes {
-        let mut leaf_cube = true;
-        for check_cube in &cubes {
-            if *cube == *check_cube {
-                continue;
+    // How many of the recorded cubes `cube` extends, counting itself
+    pub fn depth(&self, cube: &Cube) -> usize {
+        let mut node = 0;
+        let mut depth = 0;
+        for lit in &cube.0 {
+            match self.nodes[node].children.get(lit) {
+                Some(child) => node = *child,
+                None => break,
             }
-            if cube.subsumes(check_cube) {
-                leaf_cube = false;
-                break;
+            depth += self.nodes[node].recorded as usize;
+        }
+        depth
+    }
+
+    // How many literals the longest recorded cube `cube` strictly extends has, which is
+    // the cube it was split from
+    fn parent_len(&self, cube: &Cube) -> Option<usize> {
+        let mut node = 0;
+        let mut parent_len = None;
+        for (i, lit) in cube.0.iter().enumerate().take(cube.0.len().saturating_sub(1)) {
+            match self.nodes[node].children.get(lit) {
+                Some(child) => node = *child,
+                None => break,
+            }
+            if self.nodes[node].recorded {
+                parent_len = Some(i + 1);
             }
         }
-        if leaf_cube {
-            leaf_cubes.push(cube.clone());
+        parent_len
+    }
+}
+
+// The cubes which were never split, in the order they were recorded, each once
+fn leaf_cubes(trie: &CubeTrie, cubes: &[Cube]) -> Vec<Cube> {
+    let mut seen = HashSet::new();
+    cubes
+        .iter()
+        .filter(|cube| trie.is_leaf(cube) && seen.insert(&cube.0))
+        .cloned()
+        .collect()
+}
+
+// Merges the cubes of a split back into the cube they were split from, as long as the
+// leaves below it take less than `threshold` together, starting from the deepest splits.
+// A merged cube gets the sum of the metrics of the leaves below it, and a leaf without a
+// metric keeps every cube above it split.
+fn coarsen(trie: &CubeTrie, root: &Cube, cubes: &[Cube], leaves: Vec<Leaf>, threshold: f32) -> Vec<Leaf> {
+    let mut children: HashMap<&[i32], Vec<&[i32]>> = HashMap::new();
+    let mut seen = HashSet::new();
+    for cube in cubes.iter().filter(|cube| seen.insert(&cube.0)) {
+        let parent = trie.parent_len(cube).map_or(&root.0[..], |len| &cube.0[..len]);
+        children.entry(parent).or_default().push(&cube.0);
+    }
+    let leaf_metrics = leaves
+        .iter()
+        .map(|leaf| (&leaf.cube.0[..], leaf.metric))
+        .collect::<HashMap<_, _>>();
+
+    // The cost of the leaves below `cube`, if they were all merged into it
+    fn merge<'a>(
+        cube: &'a [i32],
+        children: &HashMap<&[i32], Vec<&'a [i32]>>,
+        leaf_metrics: &HashMap<&[i32], Option<f32>>,
+        threshold: f32,
+        merged: &mut HashMap<&'a [i32], f32>,
+    ) -> Option<f32> {
+        let Some(kids) = children.get(cube) else {
+            return leaf_metrics.get(cube).copied().flatten();
+        };
+        // every child is visited, so merges further down happen regardless of this one
+        let costs = kids
+            .iter()
+            .map(|kid| merge(kid, children, leaf_metrics, threshold, merged))
+            .collect::<Vec<_>>();
+        let total = costs.into_iter().sum::<Option<f32>>()?;
+        if total < threshold {
+            merged.insert(cube, total);
+            Some(total)
+        } else {
+            None
         }
     }
+    let mut merged = HashMap::new();
+    merge(&root.0, &children, &leaf_metrics, threshold, &mut merged);
+
+    let mut coarse = Vec::with_capacity(leaves.len());
+    let mut emitted = HashSet::new();
+    for leaf in leaves {
+        let highest = (root.0.len()..leaf.cube.0.len()).find(|len| merged.contains_key(&leaf.cube.0[..*len]));
+        match highest {
+            Some(len) => {
+                let cube = Cube(leaf.cube.0[..len].to_vec());
+                if emitted.insert(cube.0.clone()) {
+                    let metric = Some(merged[&cube.0[..]]);
+                    coarse.push(Leaf { metric, depth: trie.depth(&cube), cube });
+                }
+            }
+            None => coarse.push(leaf),
+        }
+    }
+    coarse
+}
+
+// The leaves of the tree rooted at `root` recorded in `log_loc`, after coarsening below
+// `coarsen_threshold` when there is one. If `root` was never split, it is the only leaf.
+pub fn subtree_leaves(log_loc: &str, root: &Cube, coarsen_threshold: Option<f32>) -> Result<Vec<Leaf>, io::Error> {
+    let logged = if Path::new(log_loc).exists() {
+        read_logged_cubes(log_loc)?
+    } else {
+        Vec::new()
+    };
+    let cubes = logged.iter().map(|(cube, _)| cube.clone()).collect::<Vec<_>>();
+    let metrics = logged
+        .iter()
+        .map(|(cube, metric)| (&cube.0, *metric))
+        .collect::<HashMap<_, _>>();
+    let trie = CubeTrie::new(&cubes);
+
+    let leaves = leaf_cubes(&trie, &cubes)
+        .into_iter()
+        .map(|cube| Leaf { metric: metrics[&cube.0], depth: trie.depth(&cube), cube })
+        .collect::<Vec<_>>();
+
+    if leaves.is_empty() {
+        Ok(vec![Leaf { cube: root.clone(), metric: None, depth: 0 }])
+    } else if let Some(threshold) = coarsen_threshold {
+        Ok(coarsen(&trie, root, &cubes, leaves, threshold))
+    } else {
+        Ok(leaves)
+    }
+}
 
-    Ok(leaf_cubes)
+// Writes the leaves as `a ... 0` lines. With a base formula, the output is a complete
+// incremental CNF which starts with the formula, and every cube is preceded by comments
+// with its metric and depth. The file is written next to `output_loc` and then moved
+// over it, so readers never see half of it.
+pub fn write_cubes(leaves: &[Leaf], output_loc: &str, base: Option<&Cnf>) -> Result<(), io::Error> {
+    let formula = base.map(Cnf::to_inccnf_string);
+    write_cubes_after(leaves, output_loc, formula.as_deref())
 }
 
-pub fn parse_logs(log_loc: &str, output_loc: &str) -> Result<(), io::Error> {
-    let leaves = parse_leaf_cubes(log_loc)?;
-    let mut outfile = OpenOptions::new().write(true).create(true).open(output_loc)?;
+// Like `write_cubes` with the formula of a full inccnf already rendered, for callers which
+// write the same formula many times
+pub fn write_cubes_after(leaves: &[Leaf], output_loc: &str, formula: Option<&str>) -> Result<(), io::Error> {
+    let tmp_loc = format!("{output_loc}.tmp");
+    let outfile = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp_loc)?;
+    let mut outfile = BufWriter::new(outfile);
+    if let Some(formula) = formula {
+        outfile.write_all(formula.as_bytes())?;
+    }
     for leaf in leaves {
-        let out_line = format!("a {} 0\n", leaf.to_string().replace('_', " ").replace('n', "-"));
+        if formula.is_some() {
+            let metric = leaf.metric.map_or("none".to_string(), |m| format!("{m:?}"));
+            outfile.write_all(format!("c metric: {}\nc depth: {}\n", metric, leaf.depth).as_bytes())?;
+        }
+        let out_line = format!("a {}\n", leaf.cube.0.iter().chain(&[0]).join(" "));
         outfile.write_all(out_line.as_bytes())?;
     }
-    Ok(())
+    outfile.flush()?;
+    drop(outfile);
+    fs::rename(tmp_loc, output_loc)
+}
+
+pub fn parse_logs(
+    log_loc: &str,
+    output_loc: &str,
+    base: Option<&Cnf>,
+    coarsen_threshold: Option<f32>,
+) -> Result<Vec<Leaf>, io::Error> {
+    let leaves = subtree_leaves(log_loc, &Cube(Vec::new()), coarsen_threshold)?;
+    write_cubes(&leaves, output_loc, base)?;
+    Ok(leaves)
+}
+
+// Merges the subtrees grown under multitree variables or starting cubes, which each live
+// in the subdirectory named after their starter cube.
+pub fn parse_multitree_logs(
+    output_dir: &str,
+    starter_cubes: &[Cube],
+    output_loc: &str,
+    base: Option<&Cnf>,
+    coarsen_threshold: Option<f32>,
+) -> Result<Vec<Leaf>, io::Error> {
+    let mut leaves = Vec::new();
+    for starter_cube in starter_cubes {
+        leaves.extend(subtree_leaves(
+            &format!("{}/{}/best.log", output_dir, starter_cube.file_name()),
+            starter_cube,
+            coarsen_threshold,
+        )?);
+    }
+    write_cubes(&leaves, output_loc, base)?;
+    Ok(leaves)
+}
+
+// A summary of the leaves, printed at the end of a run. Leaves without a metric are
+// counted but left out of the score, and the makespan is only estimated given workers.
+pub fn leaf_report(comparator: &Comparator, conquer_workers: Option<usize>, leaves: &[Leaf]) -> String {
+    let metrics = leaves.iter().filter_map(|leaf| leaf.metric).collect::<Vec<_>>();
+    let mut lines = vec![format!("Leaves: {} ({} with a metric)", leaves.len(), metrics.len())];
+    if let Some(depth) = leaves.iter().map(|leaf| leaf.depth).max().filter(|d| *d > 0) {
+        lines.push(format!("Deepest leaf: {depth} splits"));
+    }
+    if !metrics.is_empty() {
+        lines.push(format!("Score ({}): {:?}", comparator, comparator.score(&metrics)));
+        lines.push(format!(
+            "Metrics: sum {:?}, min {:?}, max {:?}",
+            metrics.iter().sum::<f32>(),
+            metrics.iter().copied().fold(f32::MAX, f32::min),
+            metrics.iter().copied().fold(f32::MIN, f32::max),
+        ));
+        if let Some(workers) = conquer_workers {
+            let makespan = lpt_makespan(metrics.iter().copied(), workers);
+            lines.push(format!("Estimated makespan on {workers} workers: {makespan:?}"));
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::seq::SliceRandom;
+    use rand::Rng;
+    use std::fs;
+
+    // The leaf extraction this module used before the trie, which compares every pair of cubes
+    fn leaf_cubes_by_subsumption(cubes: &[Cube]) -> Vec<Cube> {
+        let mut leaf_cubes = Vec::new();
+        for cube in cubes {
+            let mut leaf_cube = true;
+            for check_cube in cubes {
+                if *cube == *check_cube {
+                    continue;
+                }
+                if cube.subsumes(check_cube) {
+                    leaf_cube = false;
+                    break;
+                }
+            }
+            if leaf_cube {
+                leaf_cubes.push(cube.clone());
+            }
+        }
+        leaf_cubes
+    }
+
+    // Splits random leaves on one or two unused variables, like tree_gen does
+    fn random_tree(rng: &mut impl Rng, splits: usize) -> Vec<Cube> {
+        let mut leaves = vec![Cube(Vec::new())];
+        let mut recorded = Vec::new();
+        for _ in 0..splits {
+            let leaf = leaves.swap_remove(rng.gen_range(0..leaves.len()));
+            let mut vars = (1..40).filter(|v| !leaf.contains_var(*v)).collect::<Vec<_>>();
+            vars.shuffle(rng);
+            vars.truncate(rng.gen_range(1..=2));
+            let children = crate::runners::hyper_vec(&mut vars)
+                .into_iter()
+                .map(|lits| leaf.extend_vars(lits))
+                .collect::<Vec<_>>();
+            recorded.extend(children.iter().cloned());
+            leaves.extend(children);
+        }
+        recorded
+    }
+
+    #[test]
+    fn trie_matches_subsumption() {
+        let mut rng = rand::thread_rng();
+        for splits in [0, 1, 5, 50, 300] {
+            let cubes = random_tree(&mut rng, splits);
+            assert_eq!(
+                leaf_cubes(&CubeTrie::new(&cubes), &cubes),
+                leaf_cubes_by_subsumption(&cubes)
+            );
+        }
+    }
+
+    #[test]
+    fn repeated_cubes_are_one_leaf() {
+        let cubes = ["1", "n1", "1_2", "1_n2", "n1"].map(|c| c.parse::<Cube>().unwrap());
+        let leaves = leaf_cubes(&CubeTrie::new(&cubes), &cubes);
+        assert_eq!(leaves, ["n1", "1_2", "1_n2"].map(|c| c.parse::<Cube>().unwrap()));
+    }
+
+    #[test]
+    fn large_logs_are_fast() {
+        let cubes = random_tree(&mut rand::thread_rng(), 20000);
+        let start = std::time::Instant::now();
+        assert_eq!(
+            leaf_cubes(&CubeTrie::new(&cubes), &cubes).len(),
+            cubes.len() - 20000 + 1
+        );
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    }
+
+    #[test]
+    fn full_inccnf_output() {
+        let dir = crate::test_utils::test_dir("inccnf");
+        let log_loc = dir.join("best.log");
+        let output_loc = dir.join("cubes.icnf");
+        fs::write(&log_loc, "1: 4.0\nn1: 3.5\n1_2: 2.0\n1_n2: 1.25\n").unwrap();
+        // stale output from an earlier run
+        fs::write(&output_loc, "a 5 6 7 8 9 0\n".repeat(10)).unwrap();
+        let cnf = "p cnf 2 2\n1 2 0\n-1 -2 0\n".parse::<Cnf>().unwrap();
+
+        let (log_loc, output_loc) = (log_loc.to_str().unwrap(), output_loc.to_str().unwrap());
+        parse_logs(log_loc, output_loc, Some(&cnf), None).unwrap();
+        assert_eq!(
+            fs::read_to_string(output_loc).unwrap(),
+            concat!(
+                "p inccnf\n1 2 0\n-1 -2 0\n",
+                "c metric: 3.5\nc depth: 1\na -1 0\n",
+                "c metric: 2.0\nc depth: 2\na 1 2 0\n",
+                "c metric: 1.25\nc depth: 2\na 1 -2 0\n",
+            )
+        );
+
+        parse_logs(log_loc, output_loc, None, None).unwrap();
+        assert_eq!(fs::read_to_string(output_loc).unwrap(), "a -1 0\na 1 2 0\na 1 -2 0\n");
+    }
+
+    #[test]
+    fn report_skips_unmeasured_leaves() {
+        let leaf = |metric, depth| Leaf { cube: Cube(Vec::new()), metric, depth };
+        let leaves = [
+            leaf(Some(3.0), 2),
+            leaf(None, 0),
+            leaf(Some(1.0), 1),
+            leaf(Some(2.0), 1),
+        ];
+        assert_eq!(
+            leaf_report(&"minsum".parse().unwrap(), Some(2), &leaves),
+            concat!(
+                "Leaves: 4 (3 with a metric)\n",
+                "Deepest leaf: 2 splits\n",
+                "Score (min of sums): 6.0\n",
+                "Metrics: sum 6.0, min 1.0, max 3.0\n",
+                "Estimated makespan on 2 workers: 3.0",
+            )
+        );
+        assert_eq!(
+            leaf_report(&Comparator::MIN_OF_MAX, None, &leaves[1..2]),
+            "Leaves: 1 (0 with a metric)"
+        );
+    }
+
+    #[test]
+    fn coarsening_merges_cheap_splits() {
+        let dir = crate::test_utils::test_dir("coarsen");
+        let log_loc = dir.join("best.log");
+        fs::write(
+            &log_loc,
+            "1: 8.0\nn1: 3.0\n1_2: 0.5\n1_n2: 0.25\n1_2_3: 0.125\n1_2_n3: 0.125\n",
+        )
+        .unwrap();
+        let coarse = |threshold| {
+            subtree_leaves(log_loc.to_str().unwrap(), &Cube(Vec::new()), threshold)
+                .unwrap()
+                .into_iter()
+                .map(|leaf| (leaf.cube.to_string(), leaf.metric, leaf.depth))
+                .collect::<Vec<_>>()
+        };
+        let leaf = |cube: &str, metric, depth| (cube.to_string(), Some(metric), depth);
+
+        assert_eq!(coarse(Some(0.1)).len(), 4);
+        assert_eq!(
+            coarse(Some(0.3)),
+            vec![leaf("n1", 3.0, 1), leaf("1_n2", 0.25, 2), leaf("1_2", 0.25, 2)]
+        );
+        // 1_2 is merged first, and then 1 as well
+        assert_eq!(coarse(Some(1.0)), vec![leaf("n1", 3.0, 1), leaf("1", 0.5, 1)]);
+        assert_eq!(coarse(Some(10.0)), vec![leaf("", 3.5, 0)]);
+    }
 }
@@ -0,0 +1,149 @@
+use crate::comparator::Comparator;
+use crate::cube::{read_cube_file, Cube};
+use crate::reconstruct::{read_logged_cubes, subtree_leaves, CubeTrie, Leaf};
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// The leaves of an earlier run, as written to its cubes.icnf. Metrics and depths come from
+// the comments of a full inccnf, and otherwise from every best.log of the run. Leaves which
+// were never measured, like starting cubes which were never split, have no metric.
+pub fn read_run_leaves(run_dir: &str) -> Result<Vec<Leaf>, io::Error> {
+    let icnf_loc = format!("{run_dir}/cubes.icnf");
+    let cubes = read_cube_file(&icnf_loc)?;
+
+    let mut comments = Vec::with_capacity(cubes.len());
+    let (mut metric, mut depth) = (None, None);
+    for line in fs::read_to_string(&icnf_loc)?.lines().map(str::trim) {
+        if let Some(m) = line.strip_prefix("c metric:") {
+            metric = m.trim().parse().ok();
+        } else if let Some(d) = line.strip_prefix("c depth:") {
+            depth = d.trim().parse().ok();
+        } else if line.starts_with("a ") {
+            comments.push((metric.take(), depth.take()));
+        }
+    }
+
+    let mut logged = Vec::new();
+    collect_logged_cubes(Path::new(run_dir), &mut logged)?;
+    let trie = CubeTrie::new(&logged.iter().map(|(cube, _)| cube.clone()).collect::<Vec<_>>());
+    let logged_metrics = logged
+        .into_iter()
+        .filter_map(|(cube, metric)| Some((cube.0, metric?)))
+        .collect::<HashMap<_, _>>();
+    Ok(cubes
+        .into_iter()
+        .zip(comments)
+        .map(|(cube, (metric, depth))| Leaf {
+            metric: metric.or_else(|| logged_metrics.get(&cube.0).copied()),
+            depth: depth.unwrap_or_else(|| trie.depth(&cube)),
+            cube,
+        })
+        .collect())
+}
+
+// Subtrees of multitree runs and earlier refinements keep their best.log in a subdirectory
+fn collect_logged_cubes(dir: &Path, logged: &mut Vec<(Cube, Option<f32>)>) -> Result<(), io::Error> {
+    let best_log = dir.join("best.log");
+    if best_log.exists() {
+        logged.extend(read_logged_cubes(best_log.to_str().unwrap())?);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() && path.file_name().is_some_and(|name| name != "logs") {
+            collect_logged_cubes(&path, logged)?;
+        }
+    }
+    Ok(())
+}
+
+// The leaves whose metric is worse than `threshold`, with that metric
+pub fn hard_leaves(comparator: &Comparator, leaves: &[Leaf], threshold: f32) -> Vec<(Cube, f32)> {
+    leaves
+        .iter()
+        .filter_map(|leaf| Some((leaf.cube.clone(), leaf.metric?)))
+        .filter(|(_, metric)| comparator.beats(threshold, *metric))
+        .collect()
+}
+
+// Replaces every refined leaf by the leaves of the subtree grown under it in `output_dir`,
+// keeping the order of the earlier run. A refined leaf which couldn't be split stays as it was.
+pub fn refined_leaves(
+    output_dir: &str,
+    leaves: Vec<Leaf>,
+    refined: &[Cube],
+    coarsen_threshold: Option<f32>,
+) -> Result<Vec<Leaf>, io::Error> {
+    let refined = refined.iter().map(|cube| &cube.0).collect::<HashSet<_>>();
+    let mut output = Vec::with_capacity(leaves.len());
+    for leaf in leaves {
+        if !refined.contains(&leaf.cube.0) {
+            output.push(leaf);
+            continue;
+        }
+        let subtree = subtree_leaves(
+            &format!("{}/{}/best.log", output_dir, leaf.cube.file_name()),
+            &leaf.cube,
+            coarsen_threshold,
+        )?;
+        if subtree.len() == 1 && subtree[0].cube == leaf.cube {
+            output.push(leaf);
+            continue;
+        }
+        output.extend(
+            subtree
+                .into_iter()
+                .map(|child| Leaf { depth: leaf.depth + child.depth, ..child }),
+        );
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_dir;
+
+    fn cube(s: &str) -> Cube {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn refines_only_hard_leaves() {
+        let run = test_dir("refine_run");
+        fs::write(run.join("best.log"), "1: 8.0\nn1: 3.0\n1_2: 6.0\n1_n2: 1.0\n").unwrap();
+        fs::write(run.join("cubes.icnf"), "a -1 0\na 1 2 0\na 1 -2 0\n").unwrap();
+        let leaves = read_run_leaves(run.to_str().unwrap()).unwrap();
+        let found = leaves.iter().map(|leaf| (leaf.metric, leaf.depth)).collect::<Vec<_>>();
+        assert_eq!(found, vec![(Some(3.0), 1), (Some(6.0), 2), (Some(1.0), 2)]);
+
+        let hard = hard_leaves(&Comparator::MIN_OF_MAX, &leaves, 2.5);
+        assert_eq!(hard, vec![(cube("n1"), 3.0), (cube("1_2"), 6.0)]);
+
+        // only 1_2 could be split further
+        let out = test_dir("refine_out");
+        fs::create_dir(out.join(cube("1_2").file_name())).unwrap();
+        fs::write(out.join("1_2/best.log"), "1_2_3: 2.0\n1_2_n3: 2.5\n").unwrap();
+        let refined = refined_leaves(out.to_str().unwrap(), leaves, &[cube("n1"), cube("1_2")], None).unwrap();
+        let cubes = refined.iter().map(|leaf| leaf.cube.to_string()).collect::<Vec<_>>();
+        assert_eq!(cubes, vec!["n1", "1_2_3", "1_2_n3", "1_n2"]);
+        assert_eq!((refined[1].metric, refined[1].depth), (Some(2.0), 3));
+    }
+
+    #[test]
+    fn full_inccnf_comments_are_used() {
+        let run = test_dir("refine_comments");
+        fs::create_dir_all(run.join("3")).unwrap();
+        fs::write(run.join("3/best.log"), "3_1: 4.0\n3_n1: 5.0\n").unwrap();
+        fs::write(
+            run.join("cubes.icnf"),
+            "p inccnf\n1 2 0\nc metric: 7.5\nc depth: 2\na -3 2 0\na 3 1 0\nc metric: none\nc depth: 1\na 3 -1 0\n",
+        )
+        .unwrap();
+        let leaves = read_run_leaves(run.to_str().unwrap()).unwrap();
+        let found = leaves.iter().map(|leaf| (leaf.metric, leaf.depth)).collect::<Vec<_>>();
+        assert_eq!(found, vec![(Some(7.5), 2), (Some(4.0), 1), (Some(5.0), 1)]);
+    }
+}
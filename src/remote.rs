@@ -0,0 +1,474 @@
+use crate::config::Config;
+use crate::cube::Cube;
+use crate::runners::{check_metrics, log_location, run_solver, SolverFailure, SolverResult};
+use crate::shutdown;
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+
+use serde_json::{json, Value};
+
+// The protocol is one JSON object per line.
+// A worker opens with {"slots": n}, after which the coordinator sends
+// {"id": .., "cube": .., "timeout": ..} jobs and the worker answers each with
+// {"id": .., "status": "finished" | "timeout" | "memout" | "failed" | "error", ...}.
+// When the coordinator shuts down it hangs up, and the workers kill the jobs they are running.
+
+static DISPATCHER: OnceLock<Arc<Dispatcher>> = OnceLock::new();
+
+// When this process is a coordinator, every solver call goes through this dispatcher
+pub fn dispatcher() -> Option<&'static Arc<Dispatcher>> {
+    DISPATCHER.get()
+}
+
+pub fn install_dispatcher(dispatcher: Arc<Dispatcher>) {
+    if DISPATCHER.set(dispatcher).is_err() {
+        panic!("Tried to install a second dispatcher");
+    }
+}
+
+// An error is something going wrong on the worker other than the solver failing
+type JobResult = Result<SolverResult, String>;
+
+struct Job {
+    id: u64,
+    cube: Cube,
+    timeout: f32,
+    reply: Sender<JobResult>,
+}
+
+struct ConnectionState {
+    in_flight: HashMap<u64, Job>,
+    alive: bool,
+}
+
+struct Connection {
+    state: Mutex<ConnectionState>,
+    freed: Condvar,
+}
+
+pub struct Dispatcher {
+    addr: SocketAddr,
+    queue: Mutex<VecDeque<Job>>,
+    available: Condvar,
+    next_id: AtomicU64,
+}
+
+impl Dispatcher {
+    // Binds to `addr` and accepts workers in the background.
+    pub fn listen(addr: impl ToSocketAddrs) -> Result<Arc<Dispatcher>, io::Error> {
+        let listener = TcpListener::bind(addr)?;
+        let dispatcher = Arc::new(Dispatcher {
+            addr: listener.local_addr()?,
+            queue: Mutex::new(VecDeque::new()),
+            available: Condvar::new(),
+            next_id: AtomicU64::new(0),
+        });
+
+        let accepting = Arc::clone(&dispatcher);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let serving = Arc::clone(&accepting);
+                thread::spawn(move || {
+                    if let Err(e) = serving.serve_worker(stream) {
+                        println!("Lost connection to worker: {e}");
+                    }
+                });
+            }
+        });
+
+        Ok(dispatcher)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Stands in for spawning the solver locally. The worker's config may track other
+    // metrics, so they are checked against the coordinator's config again.
+    pub fn run(&self, config: &Config, cube: &Cube, timeout_time: f32) -> Result<SolverResult, io::Error> {
+        match self.submit(cube, timeout_time).map_err(io::Error::other)? {
+            SolverResult::Finished(metrics) => Ok(check_metrics(config, metrics)),
+            res => Ok(res),
+        }
+    }
+
+    // Blocks until some worker has finished the job.
+    fn submit(&self, cube: &Cube, timeout: f32) -> JobResult {
+        if shutdown::requested() {
+            return Ok(SolverResult::Timeout);
+        }
+        let (reply, result) = channel();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.queue
+            .lock()
+            .unwrap()
+            .push_back(Job { id, cube: cube.clone(), timeout, reply });
+        self.available.notify_one();
+
+        // When splits is shutting down the job is abandoned, just like a local solver would be
+        // killed, and the connection it was sent on is closed, which drops it.
+        loop {
+            match result.recv_timeout(shutdown::POLL_INTERVAL) {
+                Ok(res) => return res,
+                Err(_) if shutdown::requested() => return Ok(SolverResult::Timeout),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return Err(format!("Job for cube {cube} was dropped")),
+            }
+        }
+    }
+
+    fn next_job(&self) -> Job {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            match queue.pop_front() {
+                Some(job) => return job,
+                None => queue = self.available.wait(queue).unwrap(),
+            }
+        }
+    }
+
+    // Jobs of a lost worker go to the front so they don't wait behind newer ones.
+    fn requeue(&self, jobs: Vec<Job>) {
+        if jobs.is_empty() {
+            return;
+        }
+        let mut queue = self.queue.lock().unwrap();
+        for job in jobs {
+            println!("Requeueing cube {}", job.cube);
+            queue.push_front(job);
+        }
+        self.available.notify_all();
+    }
+
+    // The jobs of the connection are abandoned when splits is shutting down
+    fn drop_connection(&self, connection: &Connection) {
+        let mut state = connection.state.lock().unwrap();
+        state.alive = false;
+        let jobs = state.in_flight.drain().map(|(_, job)| job).collect();
+        drop(state);
+        connection.freed.notify_all();
+        if !shutdown::requested() {
+            self.requeue(jobs);
+        }
+    }
+
+    fn serve_worker(self: &Arc<Self>, stream: TcpStream) -> Result<(), io::Error> {
+        let peer = stream.peer_addr()?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        let mut hello = String::new();
+        reader.read_line(&mut hello)?;
+        let slots = serde_json::from_str::<Value>(&hello)?["slots"]
+            .as_u64()
+            .filter(|s| *s > 0)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Worker did not announce its slots"))?;
+        println!("Worker {peer} connected with {slots} slots");
+
+        let connection = Arc::new(Connection {
+            state: Mutex::new(ConnectionState { in_flight: HashMap::new(), alive: true }),
+            freed: Condvar::new(),
+        });
+
+        // Hanging up once splits is shutting down makes the worker kill the jobs it is running
+        let hangup = writer.try_clone()?;
+        let watched = Arc::clone(&connection);
+        thread::spawn(move || {
+            while !shutdown::requested() && watched.state.lock().unwrap().alive {
+                thread::sleep(shutdown::POLL_INTERVAL);
+            }
+            let _ = hangup.shutdown(Shutdown::Both);
+        });
+
+        let dispatcher = Arc::clone(self);
+        let reading = Arc::clone(&connection);
+        thread::spawn(move || {
+            if let Err(e) = dispatcher.read_results(&reading, reader) {
+                println!("Bad message from worker {peer}: {e}");
+            }
+            println!("Worker {peer} disconnected");
+            dispatcher.drop_connection(&reading);
+        });
+
+        loop {
+            // Only take a job once this worker has a free slot, otherwise it would sit
+            // here while another worker is idle.
+            {
+                let mut state = connection.state.lock().unwrap();
+                while state.alive && state.in_flight.len() >= slots as usize {
+                    state = connection.freed.wait(state).unwrap();
+                }
+                if !state.alive {
+                    return Ok(());
+                }
+            }
+
+            let job = self.next_job();
+            let line = json!({"id": job.id, "cube": job.cube.to_string(), "timeout": job.timeout}).to_string();
+            {
+                let mut state = connection.state.lock().unwrap();
+                if !state.alive {
+                    drop(state);
+                    self.requeue(vec![job]);
+                    return Ok(());
+                }
+                state.in_flight.insert(job.id, job);
+            }
+
+            if let Err(e) = writeln!(writer, "{line}") {
+                // wakes up the reading thread, which requeues everything in flight
+                let _ = writer.shutdown(Shutdown::Both);
+                self.drop_connection(&connection);
+                return Err(e);
+            }
+        }
+    }
+
+    fn read_results(&self, connection: &Connection, reader: BufReader<TcpStream>) -> Result<(), io::Error> {
+        for line in reader.lines() {
+            let msg: Value = serde_json::from_str(&line?)?;
+            let id = msg["id"]
+                .as_u64()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Result without a job id"))?;
+            let result = decode_result(&msg)?;
+
+            let job = connection.state.lock().unwrap().in_flight.remove(&id);
+            connection.freed.notify_one();
+            if let Some(job) = job {
+                let _ = job.reply.send(result);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Runs jobs from the coordinator at `addr` until it hangs up, which kills the running jobs
+// since nobody waits for them anymore. The coordinator never sends more jobs than the
+// `thread count` of this worker.
+pub fn run_worker(config: &Config, addr: &str) -> Result<(), io::Error> {
+    let stream = TcpStream::connect(addr)?;
+    let reader = BufReader::new(stream.try_clone()?);
+    let writer = Mutex::new(stream);
+    writeln!(writer.lock().unwrap(), "{}", json!({ "slots": config.thread_count }))?;
+    println!("Connected to coordinator at {addr}");
+
+    // Hanging up unblocks the loop below once splits is shutting down.
+    let hangup = writer.lock().unwrap().try_clone()?;
+    thread::spawn(move || {
+        while !shutdown::requested() {
+            thread::sleep(shutdown::POLL_INTERVAL);
+        }
+        let _ = hangup.shutdown(Shutdown::Both);
+    });
+
+    thread::scope(|scope| {
+        let mut hangup = Ok(());
+        for line in reader.lines() {
+            let job = match line.and_then(|line| Ok(serde_json::from_str::<Value>(&line)?)) {
+                Ok(job) => job,
+                Err(e) => {
+                    hangup = Err(e);
+                    break;
+                }
+            };
+            let writer = &writer;
+            scope.spawn(move || {
+                let reply = run_job(config, &job);
+                match writeln!(writer.lock().unwrap(), "{reply}") {
+                    Err(_) if shutdown::requested() => (),
+                    Err(e) => println!("Failed to send result to coordinator: {e}"),
+                    Ok(()) => (),
+                }
+            });
+        }
+        if !shutdown::requested() {
+            println!("Coordinator hung up, stopping the running jobs");
+            shutdown::stop("the coordinator hung up".to_string());
+        }
+        hangup
+    })
+}
+
+fn run_job(config: &Config, job: &Value) -> Value {
+    let id = &job["id"];
+    let cube = job["cube"].as_str().and_then(|c| c.parse::<Cube>().ok());
+    let (Some(cube), Some(timeout)) = (cube, job["timeout"].as_f64()) else {
+        return json!({"id": id, "status": "error", "message": format!("Malformed job: {job}")});
+    };
+
+    let res = run_solver(config, &cube, timeout as f32);
+    if !config.preserve_logs {
+        let _ = fs::remove_file(log_location(config, &cube));
+    }
+
+    let mut reply = match res {
+        Ok(res) => encode_result(&res),
+        Err(e) => json!({"status": "error", "message": e.to_string()}),
+    };
+    reply["id"] = id.clone();
+    reply
+}
+
+fn encode_result(res: &SolverResult) -> Value {
+    match res {
+        SolverResult::Finished(metrics) => json!({"status": "finished", "metrics": metrics}),
+        SolverResult::Timeout => json!({"status": "timeout"}),
+        SolverResult::MemoryOut => json!({"status": "memout"}),
+        SolverResult::Failed(failure) => match failure {
+            SolverFailure::Crash(sig) => json!({"status": "failed", "failure": "crash", "detail": sig}),
+            SolverFailure::BadExitCode(code) => json!({"status": "failed", "failure": "exit code", "detail": code}),
+            SolverFailure::MissingMetric(name) => {
+                json!({"status": "failed", "failure": "missing metric", "detail": name})
+            }
+            SolverFailure::Unparsable(e) => json!({"status": "failed", "failure": "unparsable", "detail": e}),
+        },
+    }
+}
+
+fn decode_result(msg: &Value) -> Result<JobResult, io::Error> {
+    let bad_message = || io::Error::new(io::ErrorKind::InvalidData, format!("Malformed result: {msg}"));
+    let detail = &msg["detail"];
+    let res = match msg["status"].as_str() {
+        Some("finished") => SolverResult::Finished(serde_json::from_value(msg["metrics"].clone())?),
+        Some("timeout") => SolverResult::Timeout,
+        Some("memout") => SolverResult::MemoryOut,
+        Some("failed") => SolverResult::Failed(match (msg["failure"].as_str(), detail.as_i64(), detail.as_str()) {
+            (Some("crash"), Some(sig), _) => SolverFailure::Crash(sig as i32),
+            (Some("exit code"), Some(code), _) => SolverFailure::BadExitCode(code as i32),
+            (Some("missing metric"), _, Some(name)) => SolverFailure::MissingMetric(name.to_string()),
+            (Some("unparsable"), _, Some(e)) => SolverFailure::Unparsable(e.to_string()),
+            _ => return Err(bad_message()),
+        }),
+        Some("error") => {
+            return Ok(Err(msg["message"]
+                .as_str()
+                .unwrap_or("Unknown worker error")
+                .to_string()))
+        }
+        _ => return Err(bad_message()),
+    };
+    Ok(Ok(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{isolated, test_config, CONSTANT_SOLVER};
+    use std::time::{Duration, Instant};
+
+    fn spawn_worker(config: &Config, dispatcher: &Dispatcher) {
+        let config = config.clone();
+        let addr = dispatcher.local_addr().to_string();
+        thread::spawn(move || run_worker(&config, &addr).unwrap());
+    }
+
+    fn finished(time: f32) -> JobResult {
+        Ok(SolverResult::Finished(HashMap::from([("time".to_string(), time)])))
+    }
+
+    #[test]
+    fn jobs_are_spread_over_workers() {
+        let config = test_config("remote_spread", CONSTANT_SOLVER, "thread count: 2");
+        let dispatcher = Dispatcher::listen("127.0.0.1:0").unwrap();
+        for _ in 0..3 {
+            spawn_worker(&config, &dispatcher);
+        }
+
+        thread::scope(|scope| {
+            let handles = (1..=12)
+                .map(|v| {
+                    let dispatcher = &dispatcher;
+                    scope.spawn(move || dispatcher.submit(&Cube(vec![v, -(v + 1)]), 5.0))
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                assert_eq!(handle.join().unwrap(), finished(1.5));
+            }
+        });
+    }
+
+    #[test]
+    fn jobs_of_disconnected_workers_are_requeued() {
+        let config = test_config("remote_requeue", CONSTANT_SOLVER, "");
+        let dispatcher = Dispatcher::listen("127.0.0.1:0").unwrap();
+
+        // a worker which takes a job and then dies
+        let mut flaky = TcpStream::connect(dispatcher.local_addr()).unwrap();
+        writeln!(flaky, "{}", json!({"slots": 1})).unwrap();
+
+        thread::scope(|scope| {
+            let handle = scope.spawn(|| dispatcher.submit(&Cube(vec![1, 2]), 5.0));
+
+            let mut job = String::new();
+            BufReader::new(flaky.try_clone().unwrap()).read_line(&mut job).unwrap();
+            assert_eq!(serde_json::from_str::<Value>(&job).unwrap()["cube"], "1_2");
+            drop(flaky);
+
+            spawn_worker(&config, &dispatcher);
+            assert_eq!(handle.join().unwrap(), finished(1.5));
+        });
+    }
+
+    #[test]
+    fn shutting_down_hangs_up_on_workers() {
+        if !isolated("remote::tests::shutting_down_hangs_up_on_workers") {
+            return;
+        }
+        let dispatcher = Dispatcher::listen("127.0.0.1:0").unwrap();
+        let worker = TcpStream::connect(dispatcher.local_addr()).unwrap();
+        writeln!(&worker, "{}", json!({"slots": 1})).unwrap();
+
+        thread::scope(|scope| {
+            let handle = scope.spawn(|| dispatcher.submit(&Cube(vec![1]), 5.0));
+            let mut reader = BufReader::new(worker.try_clone().unwrap());
+            let mut job = String::new();
+            reader.read_line(&mut job).unwrap();
+            assert_eq!(serde_json::from_str::<Value>(&job).unwrap()["cube"], "1");
+
+            shutdown::stop("the test is over".to_string());
+            assert_eq!(handle.join().unwrap(), Ok(SolverResult::Timeout));
+            worker.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut rest = String::new();
+            assert_eq!(reader.read_line(&mut rest).unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn workers_kill_their_jobs_when_the_coordinator_hangs_up() {
+        if !isolated("remote::tests::workers_kill_their_jobs_when_the_coordinator_hangs_up") {
+            return;
+        }
+        let solver = "#!/bin/sh\ntouch \"$(dirname \"$0\")/started\"\nexec sleep 30\n";
+        let config = test_config("remote_hangup", solver, "kill grace period: 0.5");
+        let started = std::path::Path::new(&config.output_dir)
+            .parent()
+            .unwrap()
+            .join("started");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let worker = thread::spawn(move || run_worker(&config, &addr));
+
+        let (mut coordinator, _) = listener.accept().unwrap();
+        let mut hello = String::new();
+        BufReader::new(coordinator.try_clone().unwrap())
+            .read_line(&mut hello)
+            .unwrap();
+        writeln!(coordinator, "{}", json!({"id": 0, "cube": "1", "timeout": 60.0})).unwrap();
+        while !started.exists() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let hangup = Instant::now();
+        drop(coordinator);
+
+        worker.join().unwrap().unwrap();
+        assert!(hangup.elapsed() < Duration::from_secs(10));
+        assert_eq!(shutdown::stop_reason(), Some("the coordinator hung up"));
+    }
+}
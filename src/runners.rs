@@ -1,19 +1,28 @@
-use crate::config::{
-    Comparator::{MaxOfMin, MinOfMax},
-    Config,
-};
+use crate::budget;
+use crate::cache;
+use crate::config::Config;
 use crate::cube::{neg_var, pos_var, Cube};
+use crate::frontier::Frontier;
+use crate::limits::limit_solver;
+use crate::remote;
+use crate::shutdown;
 
-use std::cmp::Ordering;
-use std::collections::{hash_map::Entry, HashMap};
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::fmt;
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, Read, Write};
-use std::process::{exit, Command};
+use std::os::unix::process::CommandExt;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicU32, Ordering as AtomicOrdering};
 use std::sync::mpsc::channel;
-use std::time::Duration;
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 use itertools::Itertools;
+use nix::errno::Errno;
 use nix::sys::signal::{self, Signal};
+use nix::sys::wait::{waitid, Id, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use rayon::ThreadPool;
@@ -21,10 +30,10 @@ use sysinfo::System;
 use wait_timeout::ChildExt;
 
 fn done_check(config: &Config, cube_vars: &[i32]) -> bool {
-    return config
+    config
         .variables
         .iter()
-        .all(|x| Cube(cube_vars.to_vec()).contains_var(*x));
+        .all(|x| Cube(cube_vars.to_vec()).contains_var(*x))
 }
 
 // this destroys v
@@ -46,65 +55,344 @@ pub fn hyper_vec(v: &mut Vec<u32>) -> Vec<Vec<i32>> {
     }
 }
 
-type ClassVecScores = HashMap<Vec<u32>, Vec<(Vec<i32>, Option<f32>, Option<f32>)>>;
+// What tree generation keeps of a finished solver run
+#[derive(Debug, Clone, Copy)]
+struct Score {
+    metric: f32,
+    time: f32,
+    cutoff: f32,
+}
 
-// this is some garbage code lol
-// I should fix this
-fn compare(config: &Config, hm: &ClassVecScores, prev_metric: f32) -> Option<Vec<(Vec<i32>, f32, f32)>> {
-    let cmp_helper = match config.comparator {
-        MaxOfMin => {
-            |winning: Vec<(Vec<i32>, f32, f32)>, chal: Vec<(Vec<i32>, f32, f32)>| -> Vec<(Vec<i32>, f32, f32)> {
-                let winning_min = winning.iter().map(|x| x.1).reduce(f32::min).unwrap();
-                let chal_min = chal.iter().map(|x| x.1).reduce(f32::min).unwrap();
-                if winning_min > chal_min {
-                    return winning;
-                }
-                chal
-            }
+type ClassVecScores = HashMap<Vec<u32>, Vec<(Vec<i32>, Option<Score>)>>;
+
+// The cubes a split adds to the tree, with their scores
+type ScoredClass = Vec<(Cube, Score)>;
+
+// The classes of sibling cubes where every cube improved enough on its parent
+fn candidates(config: &Config, hm: &ClassVecScores, prev_metric: f32) -> Vec<Vec<(Vec<i32>, Score)>> {
+    let bound = config.cutoff_proportion * prev_metric;
+
+    hm.values()
+        .filter(|class_vec| {
+            class_vec
+                .iter()
+                .all(|x| x.1.is_some_and(|s| config.comparator.beats(s.metric, bound)))
+        })
+        .map(|class_vec| {
+            class_vec
+                .iter()
+                .map(|v| (v.0.clone(), v.1.unwrap()))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// Picks the candidate class whose aggregated metric is best
+fn compare(config: &Config, candidates: Vec<ScoredClass>) -> Option<ScoredClass> {
+    let comparator = config.comparator;
+
+    candidates.into_iter().reduce(|winning, chal| {
+        let winning_score = comparator.score(&winning.iter().map(|x| x.1.metric).collect::<Vec<_>>());
+        let chal_score = comparator.score(&chal.iter().map(|x| x.1.metric).collect::<Vec<_>>());
+        if comparator.beats(winning_score, chal_score) {
+            return winning;
         }
-        MinOfMax => {
-            |winning: Vec<(Vec<i32>, f32, f32)>, chal: Vec<(Vec<i32>, f32, f32)>| -> Vec<(Vec<i32>, f32, f32)> {
-                let winning_max = winning.iter().map(|x| x.1).reduce(f32::max).unwrap();
-                let chal_max = chal.iter().map(|x| x.1).reduce(f32::max).unwrap();
-                if winning_max < chal_max {
-                    return winning;
-                }
-                chal
-            }
+        chal
+    })
+}
+
+pub fn log_location(config: &Config, cube: &Cube) -> String {
+    format!("{}/logs/{}.log", config.output_dir, cube.file_name())
+}
+
+// Removes the log of `cube` once its result has been read, unless logs are preserved
+pub fn remove_log(config: &Config, cube: &Cube) -> Result<(), io::Error> {
+    if config.preserve_logs {
+        return Ok(());
+    }
+    // a run of the same literals might be writing to it
+    let _running = Running::claim(cube);
+    match fs::remove_file(log_location(config, cube)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// The canonical cubes which are being run. Cubes with the same literals in another order
+// share their files, so they are run one at a time.
+static RUNNING: OnceLock<(Mutex<HashSet<Cube>>, Condvar)> = OnceLock::new();
+
+struct Running(Cube);
+
+impl Running {
+    // Waits until no other run of the same literals is going on
+    fn claim(cube: &Cube) -> Running {
+        let (running, done) = RUNNING.get_or_init(Default::default);
+        let cube = cube.canonical();
+        let mut running = running.lock().unwrap();
+        while running.contains(&cube) {
+            running = done.wait(running).unwrap();
+        }
+        running.insert(cube.clone());
+        Running(cube)
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let (running, done) = RUNNING.get().unwrap();
+        running.lock().unwrap().remove(&self.0);
+        done.notify_all();
+    }
+}
+
+static INDEXED_NAMES: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+// Records in `index.log` of `dir` which cube a hashed file name belongs to
+pub fn index_file_name(dir: &str, cube: &Cube) -> Result<(), io::Error> {
+    let cube = cube.canonical();
+    let name = cube.file_name();
+    if name == cube.to_string() {
+        return Ok(());
+    }
+    let mut indexed = INDEXED_NAMES.get_or_init(|| Mutex::new(HashSet::new())).lock().unwrap();
+    let key = format!("{dir}/{name}");
+    if indexed.contains(&key) {
+        return Ok(());
+    }
+    let mut index_file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(format!("{dir}/index.log"))?;
+    index_file.write_all(format!("{name}: {cube}\n").as_bytes())?;
+    indexed.insert(key);
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SolverFailure {
+    // Holds the signal which killed the solver
+    Crash(i32),
+    BadExitCode(i32),
+    MissingMetric(String),
+    Unparsable(String),
+}
+
+impl fmt::Display for SolverFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolverFailure::Crash(sig) => write!(f, "crashed with signal {sig}"),
+            SolverFailure::BadExitCode(code) => write!(f, "exited with code {code}"),
+            SolverFailure::MissingMetric(name) => write!(f, "metric {name} is missing"),
+            SolverFailure::Unparsable(e) => write!(f, "unparsable output ({e})"),
         }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SolverResult {
+    // Holds every metric the solver reported
+    Finished(HashMap<String, f32>),
+    Timeout,
+    MemoryOut,
+    Failed(SolverFailure),
+}
+
+// Whether the solver got as far as writing its metrics
+fn has_splits_data(log_file_location: &str) -> bool {
+    fs::read_to_string(log_file_location).is_ok_and(|log| log.contains("SPLITS DATA"))
+}
+
+// What solvers and wrappers commonly print when an allocation fails, in lower case
+const MEMORY_OUT_MARKERS: [&str; 4] = ["out of memory", "bad_alloc", "memoryerror", "cannot allocate memory"];
+
+// Under a memory limit, a failed allocation makes the solver, or the wrapper around it,
+// report running out of memory in its log before it got to write its metrics. Signals
+// alone say nothing, since `RLIMIT_AS` makes allocations fail rather than sending one.
+fn ran_out_of_memory(log_file_location: &str) -> bool {
+    if has_splits_data(log_file_location) {
+        return false;
+    }
+    let log = fs::read_to_string(log_file_location).unwrap_or_default().to_lowercase();
+    MEMORY_OUT_MARKERS.iter().any(|marker| log.contains(marker))
+}
+
+// A run only counts as finished if the evaluation metric, the cutoff and the time
+// can all be computed from its metrics.
+pub fn check_metrics(config: &Config, metrics: HashMap<String, f32>) -> SolverResult {
+    let missing = match config
+        .evaluation_metric
+        .eval(&metrics)
+        .and(config.cutoff.eval(&metrics))
+    {
+        Err(name) => Some(name),
+        Ok(_) if !metrics.contains_key("time") => Some("time".to_string()),
+        Ok(_) => None,
     };
+    match missing {
+        Some(name) => SolverResult::Failed(SolverFailure::MissingMetric(name)),
+        None => SolverResult::Finished(metrics),
+    }
+}
 
-    let candidates = match config.comparator {
-        MaxOfMin => hm
-            .values()
-            .filter(|class_vec| {
-                class_vec
-                    .iter()
-                    .all(|x| x.1.is_some() && x.1.unwrap() > config.cutoff_proportion * prev_metric)
-            })
-            .collect::<Vec<_>>(),
-        MinOfMax => hm
-            .values()
-            .filter(|class_vec| {
-                class_vec
-                    .iter()
-                    .all(|x| x.1.is_some() && x.1.unwrap() < config.cutoff_proportion * prev_metric)
-            })
-            .collect::<Vec<_>>(),
+// Reads the metrics of a solver which exited on its own, or works out why there are none.
+fn classify_run(config: &Config, log_file_location: &str, status: ExitStatus) -> SolverResult {
+    match read_metrics(log_file_location) {
+        Ok(metrics) => check_metrics(config, metrics),
+        Err(e) => SolverResult::Failed(match (status.signal(), status.code()) {
+            (Some(sig), _) => SolverFailure::Crash(sig),
+            (_, Some(code)) if code != 0 => SolverFailure::BadExitCode(code),
+            _ => SolverFailure::Unparsable(e.to_string()),
+        }),
+    }
+}
+
+// Whether a live process other than the leader is left in the process group `pgid`
+fn group_has_members(pgid: Pid) -> bool {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return false;
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<i32>().ok())
+        .filter(|pid| *pid != pgid.as_raw())
+        .any(|pid| {
+            // the fields after the command name, which can itself contain spaces, are
+            // the state, the parent and the process group
+            let Ok(stat) = fs::read_to_string(format!("/proc/{pid}/stat")) else {
+                return false;
+            };
+            let mut fields = stat.rsplit_once(')').map_or("", |(_, rest)| rest).split_whitespace();
+            let (state, pgrp) = (fields.next(), fields.nth(1));
+            state != Some("Z") && pgrp.and_then(|pgrp| pgrp.parse().ok()) == Some(pgid.as_raw())
+        })
+}
+
+// Sends SIGTERM to the process group of the solver and escalates to SIGKILL if anything
+// in the group is still running after the grace period. A wrapper can exit on SIGTERM
+// while the solver it started ignores it, so the whole group is waited for. The wrapper
+// is only reaped at the end, so its pid, and with it the group, can't be reused before.
+fn kill_solver(config: &Config, child: &mut Child) -> Result<(), io::Error> {
+    let pgid = Pid::from_raw(child.id() as i32);
+    let kill_group = |sig| match signal::killpg(pgid, sig) {
+        Err(Errno::ESRCH) => Ok(()),
+        res => res,
     };
 
-    let nice_candidates = candidates.iter().map(|class_vec| {
-        class_vec
-            .iter()
-            .map(|v| (v.0.clone(), v.1.unwrap(), v.2.unwrap()))
-            .collect::<Vec<_>>()
-    });
-    nice_candidates.reduce(cmp_helper)
+    kill_group(Signal::SIGTERM)?;
+    let deadline = Instant::now() + Duration::from_secs_f32(config.kill_grace_period);
+    loop {
+        let flags = WaitPidFlag::WEXITED | WaitPidFlag::WNOHANG | WaitPidFlag::WNOWAIT;
+        let exited = waitid(Id::Pid(pgid), flags)? != WaitStatus::StillAlive;
+        if exited && !group_has_members(pgid) {
+            break;
+        }
+        if Instant::now() >= deadline {
+            if config.debug {
+                println!("Escalating to SIGKILL for pid {pgid}");
+            }
+            kill_group(Signal::SIGKILL)?;
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    child.wait()?;
+    Ok(())
 }
 
-fn run_solver(config: &Config, cube: &Cube, timeout_time: f32) -> Result<Option<String>, io::Error> {
+// The timeout of solver runs, which can be lowered while they are running
+pub struct TimeLimit(AtomicU32);
+
+impl TimeLimit {
+    pub fn new(timeout_time: f32) -> TimeLimit {
+        TimeLimit(AtomicU32::new(timeout_time.max(0.0).to_bits()))
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(AtomicOrdering::SeqCst))
+    }
+
+    // Non-negative floats order the same way as their bits
+    pub fn tighten(&self, timeout_time: f32) {
+        self.0
+            .fetch_min(timeout_time.max(0.0).to_bits(), AtomicOrdering::SeqCst);
+    }
+}
+
+// Waits for the solver to exit, giving up at the time limit or when splits is shutting down.
+fn wait_for_solver(child: &mut Child, limit: &TimeLimit) -> Result<Option<ExitStatus>, io::Error> {
+    let start = Instant::now();
+    loop {
+        let deadline = start + Duration::from_secs_f32(limit.get());
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if let Some(status) = child.wait_timeout(remaining.min(shutdown::POLL_INTERVAL))? {
+            return Ok(Some(status));
+        }
+        if remaining <= shutdown::POLL_INTERVAL || shutdown::requested() {
+            return Ok(None);
+        }
+    }
+}
+
+// Runs the solver on `cube`, retrying failed runs up to `solver retries` times.
+// On a coordinator, the workers take care of retrying.
+pub fn run_solver(config: &Config, cube: &Cube, timeout_time: f32) -> Result<SolverResult, io::Error> {
+    run_solver_limited(config, cube, &TimeLimit::new(timeout_time))
+}
+
+// Like `run_solver`, but a local solver is killed as soon as it passes `limit`, even if
+// `limit` was lowered after it started. Remote runs get the limit at the time they start.
+// A cube whose literals are already being run waits for that run and reuses its result.
+pub fn run_solver_limited(config: &Config, cube: &Cube, limit: &TimeLimit) -> Result<SolverResult, io::Error> {
+    let _running = Running::claim(cube);
+    let Some(cache) = cache::cache() else {
+        return run_uncached(config, cube, limit);
+    };
+    if let Some(res) = cache.lookup(config, cube, limit.get()) {
+        if config.debug {
+            println!("Reusing the cached result of cube {cube}");
+        }
+        return Ok(res);
+    }
+    let res = run_uncached(config, cube, limit)?;
+    // A run cut short by a shutdown says nothing about the cube
+    if !shutdown::requested() {
+        cache.store(cube, limit.get(), &res)?;
+    }
+    Ok(res)
+}
+
+fn run_uncached(config: &Config, cube: &Cube, limit: &TimeLimit) -> Result<SolverResult, io::Error> {
+    // Workers retry on their own, so a remote run counts as a single call
+    if let Some(dispatcher) = remote::dispatcher() {
+        budget::count_solver_call(config);
+        return dispatcher.run(config, cube, limit.get());
+    }
+
+    let mut attempt = 0;
+    loop {
+        budget::count_solver_call(config);
+        match run_solver_once(config, cube, limit)? {
+            SolverResult::Failed(failure) if attempt < config.solver_retries && !shutdown::requested() => {
+                attempt += 1;
+                println!(
+                    "Solver {failure} on cube {cube}, retrying ({attempt}/{})",
+                    config.solver_retries
+                );
+            }
+            res => return Ok(res),
+        }
+    }
+}
+
+fn run_solver_once(config: &Config, cube: &Cube, limit: &TimeLimit) -> Result<SolverResult, io::Error> {
+    if shutdown::requested() {
+        return Ok(SolverResult::Timeout);
+    }
+
     let cnf_str = config.cnf.extend_cube_str(cube);
-    let cnf_loc = format!("{}/{}.cnf", config.tmp_dir, cube);
+    let cnf_loc = format!("{}/{}.cnf", config.tmp_dir, cube.file_name());
+    index_file_name(&config.tmp_dir, cube)?;
     let mut cnf_file = File::create(&cnf_loc)?;
     cnf_file.write_all(cnf_str.as_bytes())?;
 
@@ -119,25 +407,35 @@ fn run_solver(config: &Config, cube: &Cube, timeout_time: f32) -> Result<Option<
         println!("Number of running processes before spaawning {cube}: {cadical_counter}");
     }
 
-    let log_file_loc = format!("{}/logs/{}.log", config.output_dir, cube);
+    let log_file_loc = log_location(config, cube);
+    index_file_name(&format!("{}/logs", config.output_dir), cube)?;
 
-    let mut child = Command::new(&config.solver).args([&cnf_loc, &log_file_loc]).spawn()?;
+    let mut command = Command::new(&config.solver);
+    // Wrappers don't always forward signals, so the solver gets its own process group
+    // and on timeout the whole group is killed.
+    command.args([&cnf_loc, &log_file_loc]).process_group(0);
+    let pinned_core = limit_solver(config, &mut command);
+    let mut child = command.spawn()?;
 
-    let timeout_dur = Duration::from_secs_f32(timeout_time);
-
-    let tc = child.wait_timeout(timeout_dur)?;
+    let tc = wait_for_solver(&mut child, limit)?;
 
     let res = match tc {
-        Some(_) => Ok(Some(log_file_loc)),
+        Some(status) if config.memory_limit.is_some() && !status.success() && ran_out_of_memory(&log_file_loc) => {
+            if config.debug {
+                println!("Cube {cube} ran out of memory");
+            }
+            Ok(SolverResult::MemoryOut)
+        }
+        Some(status) => Ok(classify_run(config, &log_file_loc, status)),
         None => {
             if config.debug {
                 println!("Killing cube: {cube}");
             }
-            signal::kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM)?;
-            child.wait()?;
-            Ok(None)
+            kill_solver(config, &mut child)?;
+            Ok(SolverResult::Timeout)
         }
     };
+    drop(pinned_core);
 
     if !config.preserve_cnf {
         fs::remove_file(cnf_loc)?;
@@ -146,24 +444,29 @@ fn run_solver(config: &Config, cube: &Cube, timeout_time: f32) -> Result<Option<
     res
 }
 
-fn parse_logs(config: &Config, log_file_location: &str) -> Result<(f32, HashMap<String, f32>), io::Error> {
+// Reads the metric dictionary the solver printed after `SPLITS DATA`.
+pub fn read_metrics(log_file_location: &str) -> Result<HashMap<String, f32>, io::Error> {
     let mut log_file = File::open(log_file_location)?;
     let mut lines = String::new();
     log_file.read_to_string(&mut lines)?;
+    if !lines.contains("SPLITS DATA") {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no SPLITS DATA in the log"));
+    }
     let json_str = *lines.split("SPLITS DATA").collect::<Vec<_>>().last().unwrap();
 
-    let json: HashMap<String, f32> = serde_json::from_str(json_str.trim())?;
+    Ok(serde_json::from_str(json_str.trim())?)
+}
 
-    match json.get(&config.evaluation_metric) {
-        Some(x) => Ok((*x, json)),
-        None => {
-            println!(concat!(
-                "The evaluation metric did not appear in the output of the ",
-                "solver (or something else went wrong, but it's probably that). ",
-                "Please make sure it appears exactly as written in the config."
-            ));
-            exit(1)
-        }
+// Only called on finished runs, whose metrics have been checked
+pub fn eval_metric(config: &Config, metrics: &HashMap<String, f32>) -> f32 {
+    config.evaluation_metric.eval(metrics).unwrap()
+}
+
+fn score(config: &Config, metrics: &HashMap<String, f32>) -> Score {
+    Score {
+        metric: eval_metric(config, metrics),
+        time: metrics["time"],
+        cutoff: config.cutoff.eval(metrics).unwrap(),
     }
 }
 
@@ -183,44 +486,194 @@ pub fn preprocess(config: &Config, pool: &ThreadPool) -> Result<Vec<u32>, io::Er
         })
     });
 
-    #[allow(clippy::type_complexity)]
-    let (out_cmp, in_cmp): (fn(f32, f32) -> Ordering, fn(f32, f32) -> f32) = match config.comparator {
-        MaxOfMin => (|x, y| y.total_cmp(&x), f32::min),
-        MinOfMax => (|x, y| x.total_cmp(&y), f32::max),
-    };
-
     let mut solver_results = Vec::new();
     for (var, (pos_log, neg_log)) in receiver.iter() {
-        if let (Some(log1), Some(log2)) = (pos_log?, neg_log?) {
-            let (eval1, _) = parse_logs(config, &log1)?;
-            let (eval2, _) = parse_logs(config, &log2)?;
-            solver_results.push((var, in_cmp(eval1, eval2)))
+        remove_log(config, &Cube(vec![pos_var(var)]))?;
+        remove_log(config, &Cube(vec![neg_var(var)]))?;
+        if let (SolverResult::Finished(metrics1), SolverResult::Finished(metrics2)) = (pos_log?, neg_log?) {
+            let eval1 = eval_metric(config, &metrics1);
+            let eval2 = eval_metric(config, &metrics2);
+            solver_results.push((var, config.comparator.score(&[eval1, eval2])))
         }
     }
     if config.debug {
         println!("Solver results: {:?}", solver_results);
     }
 
-    solver_results.sort_by(|(_, x), (_, y)| out_cmp(*x, *y));
+    solver_results.sort_by(|(_, x), (_, y)| config.comparator.cmp(*x, *y));
 
     let num_vars = usize::min(solver_results.len(), config.preproc_count.unwrap());
 
     Ok(solver_results.into_iter().take(num_vars).map(|(x, _)| x).collect())
 }
 
-pub fn tree_gen(
+// A cube which still needs to be split
+struct OpenNode {
+    cube: Cube,
+    metric: f32,
+    time: f32,
+    // The splits of the cube, when a lookahead has already run them
+    splits: Option<ClassVecScores>,
+}
+
+// Runs the solver on every cube, spread over the pool
+pub fn run_cubes(
     config: &Config,
     pool: &ThreadPool,
-    ccube: &Cube,
+    cubes: Vec<Cube>,
+    timeout_time: f32,
+) -> Vec<(Cube, Result<SolverResult, io::Error>)> {
+    pool.install(|| {
+        cubes
+            .into_par_iter()
+            .map(|cube| {
+                let res = run_solver(config, &cube, timeout_time);
+                (cube, res)
+            })
+            .collect()
+    })
+}
+
+// A tightened time limit is the score of the finished class plus this proportion of it and
+// this many seconds
+const TIGHTEN_SLACK: f32 = 0.1;
+
+// Runs every cube of every class. Once all cubes of a class finished, a cube of another class
+// which runs for longer than that class's score can only lose, so the time limit of the other
+// classes drops to that score. A class with a cube which didn't finish is dropped at once.
+// This needs the comparator to minimize the max or the sum of the time. The reported time
+// usually leaves out the startup of the solver, which the time limit counts, so the lowered
+// limits get some slack.
+fn run_tightened(
+    config: &Config,
+    pool: &ThreadPool,
+    classes: Vec<Vec<Cube>>,
+    timeout_time: f32,
+) -> Vec<(Cube, Result<SolverResult, io::Error>)> {
+    let limits = classes.iter().map(|_| TimeLimit::new(timeout_time)).collect::<Vec<_>>();
+    let finished = classes.iter().map(|_| Mutex::new(Vec::new())).collect::<Vec<_>>();
+    let class_sizes = classes.iter().map(|class| class.len()).collect::<Vec<_>>();
+
+    let cubes = classes
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, class)| class.into_iter().map(move |cube| (i, cube)))
+        .collect::<Vec<_>>();
+    pool.install(|| {
+        cubes
+            .into_par_iter()
+            .map(|(i, cube)| {
+                // A dropped class can't be picked, so its remaining cubes aren't run at all
+                if limits[i].get() == 0.0 {
+                    return (cube, Ok(SolverResult::Timeout));
+                }
+                let res = run_solver_limited(config, &cube, &limits[i]);
+                match &res {
+                    Ok(SolverResult::Finished(metrics)) => {
+                        let mut class_metrics = finished[i].lock().unwrap();
+                        class_metrics.push(eval_metric(config, metrics));
+                        if class_metrics.len() == class_sizes[i] {
+                            let class_score = config.comparator.score(&class_metrics);
+                            let tightened = class_score * (1.0 + TIGHTEN_SLACK) + TIGHTEN_SLACK;
+                            if config.debug {
+                                println!("Tightening the timeout to {tightened} after cube {cube}");
+                            }
+                            limits
+                                .iter()
+                                .enumerate()
+                                .filter(|(j, _)| *j != i)
+                                .for_each(|(_, limit)| limit.tighten(tightened));
+                        }
+                    }
+                    _ => limits[i].tighten(0.0),
+                }
+                (cube, res)
+            })
+            .collect()
+    })
+}
+
+// Runs every class with `budget` seconds per cube, then keeps promoting the better half of
+// the classes to twice the budget until one class is left, which gets the full timeout.
+// Classes are ranked by how many of their cubes finished, and then by the comparator.
+// Finished cubes keep their result, so only the cubes which ran out of budget are rerun.
+fn successive_halving(
+    config: &Config,
+    pool: &ThreadPool,
+    classes: Vec<Vec<Cube>>,
     prev_metric: f32,
-    prev_time: f32,
-) -> Result<(), io::Error> {
-    let ccube_vec = &ccube.0;
+    mut budget: f32,
+    timeout_time: f32,
+) -> Vec<(Cube, Result<SolverResult, io::Error>)> {
+    let bound = config.cutoff_proportion * prev_metric;
+    let mut results = HashMap::new();
+    let mut alive = classes.clone();
 
-    if done_check(config, ccube_vec) {
-        return Ok(());
+    loop {
+        if alive.len() <= 1 {
+            budget = timeout_time;
+        }
+        let pending = alive
+            .iter()
+            .flatten()
+            .filter(|cube| !matches!(results.get(&cube.0), Some(Ok(SolverResult::Finished(_)))))
+            .cloned()
+            .collect();
+        for (cube, res) in run_cubes(config, pool, pending, budget) {
+            results.insert(cube.0, res);
+        }
+        if budget >= timeout_time || shutdown::requested() {
+            break;
+        }
+
+        // A class with a cube which failed or didn't improve enough can never be picked
+        alive.retain(|class| {
+            class.iter().all(|cube| match &results[&cube.0] {
+                Ok(SolverResult::Finished(metrics)) => config.comparator.beats(eval_metric(config, metrics), bound),
+                Ok(SolverResult::Timeout) => true,
+                _ => false,
+            })
+        });
+        let rank = |class: &Vec<Cube>| {
+            let finished = class
+                .iter()
+                .filter_map(|cube| match &results[&cube.0] {
+                    Ok(SolverResult::Finished(metrics)) => Some(eval_metric(config, metrics)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            let finished_score = match finished.is_empty() {
+                true => config.comparator.worst(),
+                false => config.comparator.score(&finished),
+            };
+            (class.len() - finished.len(), finished_score)
+        };
+        alive.sort_by(|x, y| {
+            let ((unfinished_x, score_x), (unfinished_y, score_y)) = (rank(x), rank(y));
+            unfinished_x
+                .cmp(&unfinished_y)
+                .then(config.comparator.cmp(score_x, score_y))
+        });
+        alive.truncate(alive.len().div_ceil(2));
+        budget = f32::min(budget * 2.0, timeout_time);
     }
 
+    classes
+        .into_iter()
+        .flatten()
+        .filter_map(|cube| results.remove(&cube.0).map(|res| (cube, res)))
+        .collect()
+}
+
+// Runs every split of `ccube` and records the results in all.log.
+// Returns None when the runs were cut short by a shutdown.
+fn evaluate_splits(
+    config: &Config,
+    pool: &ThreadPool,
+    ccube: &Cube,
+    prev_metric: f32,
+    prev_time: f32,
+) -> Result<Option<ClassVecScores>, io::Error> {
     let num_valid_split_vars = config.variables.len()
         - ccube
             .0
@@ -236,55 +689,70 @@ pub fn tree_gen(
         .combinations(search_depth)
         .collect::<Vec<Vec<u32>>>();
 
-    let mut commands = Vec::new();
-    for split_var_vec in &split_var_vecs {
+    let mut classes = Vec::new();
+    for split_var_vec in split_var_vecs {
         if split_var_vec.iter().any(|x| ccube.contains_var(*x)) {
             continue;
         }
         let split_vars_hc = hyper_vec(&mut split_var_vec.clone());
-        for split_var_comb in split_vars_hc {
-            let split_var_cube = ccube.extend_vars(split_var_comb);
-            commands.push(split_var_cube)
+        let class = split_vars_hc
+            .into_iter()
+            .map(|split_var_comb| ccube.extend_vars(split_var_comb))
+            .collect::<Vec<_>>();
+        // a contradictory cube has no assignments, so there is nothing to run
+        if class.iter().any(|cube| cube.is_contradictory()) {
+            continue;
         }
+        classes.push(class);
     }
 
-    let (sender, receiver) = channel();
     let timeout_time = prev_time * config.time_proportion;
-    pool.install(|| {
-        commands.into_par_iter().for_each_with(sender, |s, cube| {
-            let res = run_solver(config, &cube, timeout_time);
-            s.send((cube, res)).unwrap()
-        })
-    });
+    let solver_results = match config.successive_halving {
+        Some(initial) => successive_halving(config, pool, classes, prev_metric, initial * timeout_time, timeout_time),
+        None if config.tighten_timeouts => run_tightened(config, pool, classes, timeout_time),
+        None => run_cubes(config, pool, classes.into_iter().flatten().collect(), timeout_time),
+    };
+
+    if shutdown::requested() {
+        return Ok(None);
+    }
 
-    let solver_results = receiver.iter();
     let mut hm_results: ClassVecScores = HashMap::new();
 
     let mut all_log_file = OpenOptions::new()
-        .write(true)
         .append(true)
         .create(true)
         .open(format!("{}/all.log", config.output_dir))?;
 
     for (cube, log_loc) in solver_results {
         // println!("Writing {cube} to all.log");
-        let (eval_met, time) = match log_loc {
-            Ok(Some(log_loc)) => {
-                let (eval_met, all_met) = parse_logs(config, &log_loc)?;
-
+        let cube_score = match log_loc {
+            Ok(SolverResult::Finished(all_met)) => {
                 all_log_file.write_all(format!("{}: {:?}\n", cube, all_met).as_bytes())?;
-                (Some(eval_met), Some(*all_met.get("time").unwrap()))
+                Some(score(config, &all_met))
             }
-            Ok(None) => {
+            Ok(SolverResult::Timeout) => {
                 all_log_file.write_all(format!("{}: Timeout\n", cube).as_bytes())?;
-                (None, None)
+                None
+            }
+            Ok(SolverResult::MemoryOut) => {
+                all_log_file.write_all(format!("{}: Memory Out\n", cube).as_bytes())?;
+                None
+            }
+            // A failed cube rules out its class, just like a timeout does
+            Ok(SolverResult::Failed(failure)) => {
+                all_log_file.write_all(format!("{}: Failed, {}\n", cube, failure).as_bytes())?;
+                None
             }
             Err(e) => {
                 all_log_file.write_all(format!("{}: {}\n", cube, e).as_bytes())?;
-                (None, None)
+                None
             }
         };
 
+        // Sibling nodes share the logs directory, so only this node's logs can be removed.
+        remove_log(config, &cube)?;
+
         let class = cube
             .0
             .iter()
@@ -296,51 +764,620 @@ pub fn tree_gen(
 
         match hm_results.entry(class) {
             Entry::Occupied(mut v) => {
-                v.get_mut().push((cube.0, eval_met, time));
+                v.get_mut().push((cube.0, cube_score));
             }
             Entry::Vacant(e) => {
-                e.insert(vec![(cube.0, eval_met, time)]);
+                e.insert(vec![(cube.0, cube_score)]);
             }
         }
     }
 
-    let best_vec = compare(config, &hm_results, prev_metric);
-    let mut best_log_file = OpenOptions::new()
-        .write(true)
+    Ok(Some(hm_results))
+}
+
+// Whether a cube should be split further. With a target number of cubes, the hardest cube
+// is split next no matter the cutoff.
+fn wants_split(config: &Config, s: &Score) -> bool {
+    config.target_cubes.is_some() || config.comparator.beats(s.cutoff, s.metric)
+}
+
+// The candidate classes of a node, as the cubes they would add to the tree
+fn candidate_cubes(config: &Config, hm: &ClassVecScores, prev_metric: f32) -> Vec<ScoredClass> {
+    candidates(config, hm, prev_metric)
+        .into_iter()
+        .map(|class| class.into_iter().map(|(vars, s)| (Cube(vars), s)).collect())
+        .collect()
+}
+
+fn class_score(config: &Config, class: &[(Cube, Score)]) -> f32 {
+    config
+        .comparator
+        .score(&class.iter().map(|(_, s)| s.metric).collect::<Vec<_>>())
+}
+
+// Keeps the `beam width` best classes, splits each of their cubes one level further and
+// commits to the class whose cubes split best. The lookahead splits of the chosen class are
+// handed to its children, and every alternative is recorded in beam.log.
+// Returns None when the lookahead was cut short by a shutdown.
+#[allow(clippy::type_complexity)]
+fn beam_select(
+    config: &Config,
+    pool: &ThreadPool,
+    ccube: &Cube,
+    mut classes: Vec<ScoredClass>,
+) -> Result<Option<(ScoredClass, Vec<Option<ClassVecScores>>)>, io::Error> {
+    classes.sort_by(|x, y| config.comparator.cmp(class_score(config, x), class_score(config, y)));
+    classes.truncate(config.beam_width);
+
+    // Cubes which are already good enough stay leaves. The lookaheads of all other cubes
+    // share the pool, so a class of slow cubes doesn't hold up the others.
+    let pending = classes
+        .iter()
+        .enumerate()
+        .flat_map(|(i, class)| class.iter().enumerate().map(move |(j, (cube, s))| (i, j, cube, s)))
+        .filter(|(_, _, cube, s)| wants_split(config, s) && !done_check(config, &cube.0))
+        .collect::<Vec<_>>();
+    let mut lookaheads = pool.install(|| {
+        pending
+            .into_par_iter()
+            .map(|(i, j, cube, s)| Ok(((i, j), evaluate_splits(config, pool, cube, s.metric, s.time)?)))
+            .collect::<Result<HashMap<_, _>, io::Error>>()
+    })?;
+    if lookaheads.values().any(Option::is_none) {
+        return Ok(None);
+    }
+
+    let mut alternatives = Vec::with_capacity(classes.len());
+    for (i, class) in classes.into_iter().enumerate() {
+        let mut lookahead_metrics = Vec::with_capacity(class.len());
+        let mut lookahead_splits = Vec::with_capacity(class.len());
+        for (j, (_, s)) in class.iter().enumerate() {
+            let splits = lookaheads.remove(&(i, j)).flatten();
+            let best = splits
+                .as_ref()
+                .and_then(|splits| compare(config, candidate_cubes(config, splits, s.metric)));
+            lookahead_metrics.push(best.map_or(s.metric, |best| class_score(config, &best)));
+            lookahead_splits.push(splits);
+        }
+        let value = config.comparator.score(&lookahead_metrics);
+        alternatives.push((class, lookahead_splits, value));
+    }
+
+    let best = (0..alternatives.len())
+        .min_by(|x, y| config.comparator.cmp(alternatives[*x].2, alternatives[*y].2))
+        .unwrap();
+    let describe = |(class, _, value): &(ScoredClass, _, f32)| {
+        let vars = class[0].0 .0[ccube.0.len()..]
+            .iter()
+            .map(|x| x.unsigned_abs())
+            .join(" ");
+        format!("[{}] ({:?} -> {:?})", vars, class_score(config, class), value)
+    };
+    let discarded = alternatives
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != best)
+        .map(|(_, alt)| describe(alt))
+        .join(", ");
+    let mut beam_log_file = OpenOptions::new()
         .append(true)
         .create(true)
-        .open(format!("{}/best.log", config.output_dir))?;
+        .open(format!("{}/beam.log", config.output_dir))?;
+    beam_log_file.write_all(
+        format!(
+            "{}: chose {}, discarded {}\n",
+            ccube,
+            describe(&alternatives[best]),
+            discarded
+        )
+        .as_bytes(),
+    )?;
 
-    if !config.preserve_logs {
-        fs::remove_dir_all(format!("{}/logs", config.output_dir))?;
-        fs::create_dir(format!("{}/logs", config.output_dir))?;
+    let (class, lookahead_splits, _) = alternatives.swap_remove(best);
+    Ok(Some((class, lookahead_splits)))
+}
+
+// Runs every split of the node, records the winning class in best.log and
+// returns the children of that class which still need to be split.
+fn expand_node(
+    config: &Config,
+    pool: &ThreadPool,
+    frontier: &Frontier,
+    node: OpenNode,
+) -> Result<Vec<OpenNode>, io::Error> {
+    let ccube = &node.cube;
+    if done_check(config, &ccube.0) || shutdown::requested() {
+        return Ok(Vec::new());
     }
 
+    let hm_results = match node.splits {
+        Some(splits) => splits,
+        None => match evaluate_splits(config, pool, ccube, node.metric, node.time)? {
+            Some(splits) => splits,
+            // The runs were cut short, so this node stays a leaf.
+            None => return Ok(Vec::new()),
+        },
+    };
+
+    let mut classes = candidate_cubes(config, &hm_results, node.metric);
+    let mut lookahead_splits = Vec::new();
+    if config.beam_width > 1 && classes.len() > 1 {
+        match beam_select(config, pool, ccube, classes)? {
+            Some((best, splits)) => {
+                classes = vec![best];
+                lookahead_splits = splits;
+            }
+            None => return Ok(Vec::new()),
+        }
+    }
+    let class_metrics = |class: &[(Cube, Score)]| class.iter().map(|(c, s)| (c.clone(), s.metric)).collect::<Vec<_>>();
+    let best_vec = match config.conquer_workers {
+        Some(workers) => {
+            let metrics = classes.iter().map(|class| class_metrics(class)).collect::<Vec<_>>();
+            match frontier.split_if_faster(ccube, &metrics, workers)? {
+                Some(i) => Some(classes.swap_remove(i)),
+                None if !classes.is_empty() => {
+                    println!("Splitting cube {} does not improve the estimated makespan", ccube);
+                    return Ok(Vec::new());
+                }
+                None => None,
+            }
+        }
+        None => {
+            let best = compare(config, classes);
+            if let Some(best) = &best {
+                frontier.split(ccube, &class_metrics(best))?;
+            }
+            best
+        }
+    };
+    let mut best_log_file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(format!("{}/best.log", config.output_dir))?;
+
     match best_vec {
         Some(best_vecs) => {
-            for v in best_vecs {
-                let extension_vars = v.0.into_iter().rev().take(search_depth).rev().collect::<Vec<_>>();
-                let new_cube = ccube.extend_vars(extension_vars);
-
-                best_log_file.write_all(format!("{}: {:?}\n", &new_cube, v.1).as_bytes())?;
-                match config.comparator {
-                    MaxOfMin => {
-                        if v.1 < config.cutoff {
-                            tree_gen(config, pool, &new_cube, v.1, v.2)?
-                        }
-                    }
-                    MinOfMax => {
-                        if v.1 > config.cutoff {
-                            tree_gen(config, pool, &new_cube, v.1, v.2)?
-                        }
-                    }
+            let mut best_lines = String::new();
+            let mut children = Vec::new();
+            lookahead_splits.resize_with(best_vecs.len(), || None);
+            for ((new_cube, v), splits) in best_vecs.into_iter().zip(lookahead_splits) {
+                best_lines.push_str(&format!("{}: {:?}\n", &new_cube, v.metric));
+                // Keep splitting while the cutoff is better than the cube's metric
+                if wants_split(config, &v) {
+                    children.push(OpenNode { cube: new_cube, metric: v.metric, time: v.time, splits });
                 }
             }
+            // Siblings are written together so best.log never holds half of a split.
+            best_log_file.write_all(best_lines.as_bytes())?;
+            budget::check_leaves(config, frontier);
+            Ok(children)
         }
         None => {
             println!("Failed to find further split after cube {}", ccube);
+            Ok(Vec::new())
         }
     }
+}
+
+pub fn tree_gen(
+    config: &Config,
+    pool: &ThreadPool,
+    frontier: &Frontier,
+    ccube: &Cube,
+    prev_metric: f32,
+    prev_time: f32,
+) -> Result<(), io::Error> {
+    let root = OpenNode {
+        cube: ccube.clone(),
+        metric: prev_metric,
+        time: prev_time,
+        splits: None,
+    };
+    match config.target_cubes {
+        Some(target) => best_first(pool, frontier, vec![(config, root)], target),
+        None => grow(config, pool, frontier, root),
+    }
+}
 
+// Like `tree_gen` for several roots, which each record their tree in the output dir of their config
+pub fn tree_gen_roots(
+    pool: &ThreadPool,
+    frontier: &Frontier,
+    roots: Vec<(&Config, Cube, f32)>,
+    prev_time: f32,
+) -> Result<(), io::Error> {
+    let roots = roots
+        .into_iter()
+        .map(|(config, cube, metric)| (config, OpenNode { cube, metric, time: prev_time, splits: None }))
+        .collect::<Vec<_>>();
+    match roots.first().and_then(|(config, _)| config.target_cubes) {
+        Some(target) => best_first(pool, frontier, roots, target),
+        // Each subtree fans its solver calls out onto the same pool, so running the
+        // subtrees concurrently keeps the pool busy when one of them runs out of work.
+        None => pool.install(|| {
+            roots
+                .into_par_iter()
+                .try_for_each(|(config, root)| grow(config, pool, frontier, root))
+        }),
+    }
+}
+
+// Splits the hardest leaf of the whole frontier until there are `target` leaves. A split
+// which adds more than one cube can go past the target. Leaves which can't be split are
+// skipped, so there are fewer cubes when no leaf is left to split.
+fn best_first(
+    pool: &ThreadPool,
+    frontier: &Frontier,
+    roots: Vec<(&Config, OpenNode)>,
+    target: usize,
+) -> Result<(), io::Error> {
+    let mut open = roots;
+    while frontier.len() < target && !shutdown::requested() {
+        // the comparator orders the best first, so the hardest node is the last
+        let Some(hardest) = (0..open.len()).max_by(|x, y| {
+            let ((config, x), (_, y)) = (&open[*x], &open[*y]);
+            config.comparator.cmp(x.metric, y.metric)
+        }) else {
+            break;
+        };
+        let (config, node) = open.swap_remove(hardest);
+        let children = expand_node(config, pool, frontier, node)?;
+        open.extend(children.into_iter().map(|child| (config, child)));
+    }
     Ok(())
 }
+
+fn grow(config: &Config, pool: &ThreadPool, frontier: &Frontier, node: OpenNode) -> Result<(), io::Error> {
+    let children = expand_node(config, pool, frontier, node)?;
+
+    // Sibling subtrees are independent, so they are expanded concurrently and the
+    // solver calls of every open node share the pool.
+    pool.install(|| {
+        children
+            .into_par_iter()
+            .try_for_each(|child| grow(config, pool, frontier, child))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{test_config, CONSTANT_SOLVER};
+
+    fn classify_log(name: &str, log: &str, wait_status: i32) -> SolverResult {
+        let config = test_config(name, CONSTANT_SOLVER, "");
+        let log_loc = format!("{}/solver.log", config.output_dir);
+        fs::write(&log_loc, log).unwrap();
+        classify_run(&config, &log_loc, ExitStatus::from_raw(wait_status))
+    }
+
+    #[test]
+    fn classify_finished_run() {
+        let res = classify_log("classify_finished", "c done\nSPLITS DATA\n{\"time\": 2.0}\n", 0);
+        assert_eq!(res, SolverResult::Finished(HashMap::from([("time".to_string(), 2.0)])));
+    }
+
+    #[test]
+    fn classify_failed_runs() {
+        assert_eq!(
+            classify_log("classify_crash", "c solving\n", 11),
+            SolverResult::Failed(SolverFailure::Crash(11))
+        );
+        assert_eq!(
+            classify_log("classify_exit", "c solving\n", 3 << 8),
+            SolverResult::Failed(SolverFailure::BadExitCode(3))
+        );
+        assert_eq!(
+            classify_log("classify_missing", "SPLITS DATA\n{\"ticks\": 2.0}\n", 0),
+            SolverResult::Failed(SolverFailure::MissingMetric("time".to_string()))
+        );
+        assert!(matches!(
+            classify_log("classify_unparsable", "SPLITS DATA\n{\"time\": 2.0\n", 0),
+            SolverResult::Failed(SolverFailure::Unparsable(_))
+        ));
+    }
+
+    #[test]
+    fn failed_runs_are_retried() {
+        // fails on the first run only
+        let flaky_solver = concat!(
+            "#!/bin/sh\n",
+            "marker=\"$(dirname \"$0\")/ran_once\"\n",
+            "if [ ! -e \"$marker\" ]; then touch \"$marker\"; exit 1; fi\n",
+            "echo 'SPLITS DATA' > \"$2\"\necho '{\"time\": 1.5}' >> \"$2\"\n",
+        );
+        let config = test_config("retry", flaky_solver, "");
+        let res = run_solver(&config, &Cube(vec![1]), 5.0).unwrap();
+        assert_eq!(res, SolverResult::Finished(HashMap::from([("time".to_string(), 1.5)])));
+
+        let config = test_config("no_retry", flaky_solver, "solver retries: 0");
+        let res = run_solver(&config, &Cube(vec![1]), 5.0).unwrap();
+        assert_eq!(res, SolverResult::Failed(SolverFailure::BadExitCode(1)));
+    }
+
+    #[test]
+    fn memory_outs_are_told_apart_from_crashes() {
+        // allocates a gigabyte, or fails for another reason on negative cubes
+        let solver = concat!(
+            "#!/bin/sh\n",
+            "if grep -qx -- '-1 0' \"$1\"; then exit 3; fi\n",
+            "python3 -c 'bytearray(1 << 30)' 2>> \"$2\" || exit 1\n",
+            "echo 'SPLITS DATA' >> \"$2\"\necho '{\"time\": 1.5}' >> \"$2\"\n",
+        );
+        let config = test_config("memory_out", solver, "memory limit: 200\nsolver retries: 0");
+        assert_eq!(
+            run_solver(&config, &Cube(vec![1]), 5.0).unwrap(),
+            SolverResult::MemoryOut
+        );
+        assert_eq!(
+            run_solver(&config, &Cube(vec![-1]), 5.0).unwrap(),
+            SolverResult::Failed(SolverFailure::BadExitCode(3))
+        );
+
+        // a crash is retried like any other failure, memory limit or not
+        let config = test_config("memory_limit_crash", "#!/bin/sh\nkill -SEGV $$\n", "memory limit: 200");
+        assert_eq!(
+            run_solver(&config, &Cube(vec![1]), 5.0).unwrap(),
+            SolverResult::Failed(SolverFailure::Crash(11))
+        );
+    }
+
+    #[test]
+    fn successive_halving_drops_slow_classes() {
+        // cubes on variable 1 finish at once, cubes on variable 2 take a second
+        let split_solver = concat!(
+            "#!/bin/sh\n",
+            "if grep -qE '^-?1 0' \"$1\"; then t=0.1; else sleep 1; t=1.0; fi\n",
+            "echo 'SPLITS DATA' > \"$2\"\necho \"{\\\"time\\\": $t}\" >> \"$2\"\n",
+        );
+        let config = test_config("halving", split_solver, "thread count: 2");
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        let classes = vec![vec![Cube(vec![1]), Cube(vec![-1])], vec![Cube(vec![2]), Cube(vec![-2])]];
+
+        let results = successive_halving(&config, &pool, classes, f32::MAX, 0.3, 5.0);
+        assert_eq!(results.len(), 4);
+        for (cube, res) in results {
+            match cube.0[0].unsigned_abs() {
+                1 => assert!(matches!(res, Ok(SolverResult::Finished(_)))),
+                _ => assert!(matches!(res, Ok(SolverResult::Timeout))),
+            }
+        }
+    }
+
+    #[test]
+    fn tightened_timeouts_kill_dominated_classes() {
+        // cubes on variable 1 finish at once, cubes on variable 2 take ten seconds
+        let split_solver = concat!(
+            "#!/bin/sh\n",
+            "if grep -qE '^-?1 0' \"$1\"; then t=0.1; else sleep 10; t=10.0; fi\n",
+            "echo 'SPLITS DATA' > \"$2\"\necho \"{\\\"time\\\": $t}\" >> \"$2\"\n",
+        );
+        let config = test_config("tighten", split_solver, "thread count: 2\nkill grace period: 0");
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        let classes = vec![vec![Cube(vec![1]), Cube(vec![-1])], vec![Cube(vec![2]), Cube(vec![-2])]];
+
+        let start = Instant::now();
+        let results = run_tightened(&config, &pool, classes, 30.0);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        for (cube, res) in results {
+            match cube.0[0].unsigned_abs() {
+                1 => assert!(matches!(res, Ok(SolverResult::Finished(_)))),
+                _ => assert!(matches!(res, Ok(SolverResult::Timeout))),
+            }
+        }
+    }
+
+    #[test]
+    fn tightened_timeouts_leave_room_for_startup() {
+        // cubes on variable 2 report a better time than those on variable 1, but on the clock
+        // they take a bit longer than the score of variable 1
+        let split_solver = concat!(
+            "#!/bin/sh\n",
+            "if grep -qE '^-?1 0' \"$1\"; then t=1.0; else sleep 1.05; t=0.9; fi\n",
+            "echo 'SPLITS DATA' > \"$2\"\necho \"{\\\"time\\\": $t}\" >> \"$2\"\n",
+        );
+        let config = test_config("tighten_slack", split_solver, "thread count: 4\nkill grace period: 0");
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let classes = vec![vec![Cube(vec![1]), Cube(vec![-1])], vec![Cube(vec![2]), Cube(vec![-2])]];
+
+        for (cube, res) in run_tightened(&config, &pool, classes, 30.0) {
+            assert!(matches!(res, Ok(SolverResult::Finished(_))), "{cube} didn't finish");
+        }
+    }
+
+    #[test]
+    fn beam_search_looks_past_the_first_level() {
+        // splitting on 1 is best at first, but only the cubes on 2 split well further
+        let solver = concat!(
+            "#!/bin/sh\n",
+            "has() { grep -qxE -- \"-?$1 0\" \"$cnf\"; }\n",
+            "cnf=$1\n",
+            "if [ \"$(grep -cE '^-?[0-9]+ 0$' \"$cnf\")\" -eq 1 ]; then\n",
+            "  if has 1; then t=4; elif has 2; then t=5; else t=6; fi\n",
+            "elif has 1; then t=3.5; else t=1; fi\n",
+            "echo 'SPLITS DATA' > \"$2\"\necho \"{\\\"time\\\": $t}\" >> \"$2\"\n",
+        );
+        let cnf_loc = crate::test_utils::test_dir("beam_cnf").join("f.cnf");
+        fs::write(&cnf_loc, "p cnf 3 1\n1 2 3 0\n").unwrap();
+        let settings = format!("variables: 1 2 3\ncnf: {}\nbeam width: 2", cnf_loc.display());
+        let config = test_config("beam", solver, &settings);
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let root = Cube(Vec::new());
+
+        let splits = evaluate_splits(&config, &pool, &root, 10.0, 30.0).unwrap().unwrap();
+        let classes = candidate_cubes(&config, &splits, 10.0);
+        let (class, lookahead_splits) = beam_select(&config, &pool, &root, classes).unwrap().unwrap();
+        let cubes = class
+            .iter()
+            .map(|(cube, _)| cube.to_string())
+            .sorted()
+            .collect::<Vec<_>>();
+        assert_eq!(cubes, vec!["2", "n2"]);
+        assert!(lookahead_splits.iter().all(Option::is_some));
+        assert_eq!(
+            fs::read_to_string(format!("{}/beam.log", config.output_dir)).unwrap(),
+            ": chose [2] (5.0 -> 1.0), discarded [1] (4.0 -> 3.5)\n"
+        );
+    }
+
+    #[test]
+    fn preprocessing_ranks_variables() {
+        // both cubes on variable 2 are faster than those on variable 1
+        let solver = concat!(
+            "#!/bin/sh\n",
+            "if grep -qxE -- '-?2 0' \"$1\"; then t=1; else t=3; fi\n",
+            "echo 'SPLITS DATA' > \"$2\"\necho \"{\\\"time\\\": $t}\" >> \"$2\"\n",
+        );
+        let config = test_config("preprocess", solver, "preprocess count: 1");
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        assert_eq!(preprocess(&config, &pool).unwrap(), vec![2]);
+        assert_eq!(fs::read_dir(format!("{}/logs", config.output_dir)).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn sibling_subtrees_are_expanded_concurrently() {
+        // the root is split on 1, and the cubes under 1 and under -1 each wait for the other
+        // side to start running, and only finish fast if it did
+        let solver = concat!(
+            "#!/bin/sh\n",
+            "dir=$(dirname \"$0\")\n",
+            "if [ \"$(grep -cE '^-?[0-9]+ 0$' \"$1\")\" -le 1 ]; then\n",
+            "  if grep -qxE -- '-?1 0' \"$1\"; then t=5; else t=6; fi\n",
+            "else\n",
+            "  if grep -qx '1 0' \"$1\"; then me=pos; other=neg; else me=neg; other=pos; fi\n",
+            "  touch \"$dir/$me\"; i=0\n",
+            "  while [ ! -e \"$dir/$other\" ] && [ $i -lt 30 ]; do sleep 0.1; i=$((i + 1)); done\n",
+            "  if [ -e \"$dir/$other\" ]; then t=1; else t=9; fi\n",
+            "fi\n",
+            "echo 'SPLITS DATA' > \"$2\"\necho \"{\\\"time\\\": $t}\" >> \"$2\"\n",
+        );
+        let config = test_config("concurrent_grow", solver, "thread count: 4");
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let root = Cube(Vec::new());
+        let frontier = Frontier::new(std::slice::from_ref(&root));
+        tree_gen(&config, &pool, &frontier, &root, 10.0, 30.0).unwrap();
+
+        let best_log = fs::read_to_string(format!("{}/best.log", config.output_dir)).unwrap();
+        let mut lines = best_log.lines().collect::<Vec<_>>();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec!["1: 5.0", "1_2: 1.0", "1_n2: 1.0", "n1: 5.0", "n1_2: 1.0", "n1_n2: 1.0"]
+        );
+        assert_eq!(frontier.len(), 4);
+    }
+
+    #[test]
+    fn dropped_classes_are_not_run() {
+        // every run is counted, and the cube 1 fails
+        let solver = concat!(
+            "#!/bin/sh\n",
+            "echo run >> \"$(dirname \"$0\")/runs\"\n",
+            "if grep -qx '1 0' \"$1\"; then exit 1; fi\n",
+            "echo 'SPLITS DATA' > \"$2\"\necho '{\"time\": 0.1}' >> \"$2\"\n",
+        );
+        let config = test_config("tighten_dropped", solver, "solver retries: 0");
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let classes = vec![vec![Cube(vec![1]), Cube(vec![-1])], vec![Cube(vec![2]), Cube(vec![-2])]];
+
+        let results = run_tightened(&config, &pool, classes, 5.0);
+        assert!(matches!(results[1], (Cube(ref c), Ok(SolverResult::Timeout)) if *c == vec![-1]));
+        let runs_loc = std::path::Path::new(&config.output_dir).parent().unwrap().join("runs");
+        assert_eq!(fs::read_to_string(runs_loc).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn solvers_ignoring_sigterm_are_killed() {
+        // the wrapper exits on SIGTERM, but the solver it started ignores it
+        let wrapper = concat!(
+            "#!/bin/sh\n",
+            "sh -c \"trap '' TERM; echo \\$\\$ > '$(dirname \"$0\")/solver.pid'; exec sleep 30\" &\n",
+            "wait\n",
+        );
+        let config = test_config("kill_group", wrapper, "kill grace period: 0.5");
+        let res = run_solver(&config, &Cube(vec![1]), 0.5).unwrap();
+        assert_eq!(res, SolverResult::Timeout);
+
+        let pid_loc = std::path::Path::new(&config.output_dir)
+            .parent()
+            .unwrap()
+            .join("solver.pid");
+        let pid = fs::read_to_string(pid_loc).unwrap().trim().parse().unwrap();
+        // the killed solver is reaped by init, which can take a moment
+        let start = Instant::now();
+        while signal::kill(Pid::from_raw(pid), None).is_ok() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(signal::kill(Pid::from_raw(pid), None), Err(Errno::ESRCH));
+    }
+
+    #[test]
+    fn deep_cubes_get_hashed_file_names() {
+        let config = test_config("deep_cube", CONSTANT_SOLVER, "preserve logs: true");
+        let deep = Cube((100000..100050).collect());
+        let res = run_solver(&config, &deep, 5.0).unwrap();
+        assert!(matches!(res, SolverResult::Finished(_)));
+
+        let index = fs::read_to_string(format!("{}/logs/index.log", config.output_dir)).unwrap();
+        assert_eq!(index, format!("{}: {}\n", deep.file_name(), deep));
+        assert!(std::path::Path::new(&log_location(&config, &deep)).exists());
+    }
+
+    #[test]
+    fn reordered_cubes_wait_for_the_first_run() {
+        if !crate::test_utils::isolated("runners::tests::reordered_cubes_wait_for_the_first_run") {
+            return;
+        }
+        crate::cache::install_cache(crate::cache::Cache::in_memory());
+        // every run is counted, and takes long enough for the other cube to start meanwhile
+        let solver = concat!(
+            "#!/bin/sh\n",
+            "echo run >> \"$(dirname \"$0\")/runs\"\n",
+            "sleep 0.5\n",
+            "echo 'SPLITS DATA' > \"$2\"\necho '{\"time\": 0.5}' >> \"$2\"\n",
+        );
+        let config = test_config("reordered_cubes", solver, "");
+        let config = &config;
+        let results = std::thread::scope(|scope| {
+            let runs = [Cube(vec![1, -2]), Cube(vec![-2, 1])]
+                .map(|cube| scope.spawn(move || run_solver(config, &cube, 5.0).unwrap()));
+            runs.map(|run| run.join().unwrap())
+        });
+
+        assert!(results.iter().all(|res| matches!(res, SolverResult::Finished(_))));
+        let runs_loc = std::path::Path::new(&config.output_dir).parent().unwrap().join("runs");
+        assert_eq!(fs::read_to_string(runs_loc).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn best_first_splits_the_hardest_leaf() {
+        // splitting on variable 1 gives a hard and an easy cube, and variable 2 halves the time
+        let solver = concat!(
+            "#!/bin/sh\n",
+            "if grep -qx '1 0' \"$1\"; then t=8; elif grep -qx -- '-1 0' \"$1\"; then t=2; else t=20; fi\n",
+            "if grep -qxE -- '-?2 0' \"$1\"; then t=$((t / 2)); fi\n",
+            "echo 'SPLITS DATA' > \"$2\"\necho \"{\\\"time\\\": $t}\" >> \"$2\"\n",
+        );
+        let first = vec!["1: 8.0", "n1: 2.0"];
+        let second = vec!["1: 8.0", "1_2: 4.0", "1_n2: 4.0", "n1: 2.0"];
+        let all = vec!["1: 8.0", "1_2: 4.0", "1_n2: 4.0", "n1: 2.0", "n1_2: 1.0", "n1_n2: 1.0"];
+        for (target, expected) in [(2, first), (3, second), (100, all)] {
+            let config = test_config(
+                &format!("best_first_{target}"),
+                solver,
+                &format!("target cubes: {target}"),
+            );
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+            let root = Cube(Vec::new());
+            let frontier = Frontier::new(std::slice::from_ref(&root));
+            tree_gen(&config, &pool, &frontier, &root, config.comparator.worst(), 30.0).unwrap();
+
+            let best_log = fs::read_to_string(format!("{}/best.log", config.output_dir)).unwrap();
+            let mut lines = best_log.lines().collect::<Vec<_>>();
+            lines.sort();
+            assert_eq!(lines, expected);
+            assert_eq!(frontier.len(), target.min(4));
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use nix::libc::c_int;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+// How often anything blocking checks whether splits is shutting down
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+// Set when splits stops itself rather than on a signal
+static STOP_REASON: OnceLock<String> = OnceLock::new();
+
+extern "C" fn request_shutdown(_: c_int) {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+// After SIGINT or SIGTERM, running solvers are killed and no further nodes are expanded.
+// The solvers live in their own process groups, so they never see the signal themselves.
+pub fn install_handler() -> Result<(), io::Error> {
+    let action = SigAction::new(
+        SigHandler::Handler(request_shutdown),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for sig in [Signal::SIGINT, Signal::SIGTERM] {
+        // Safety: the handler only stores to an atomic
+        unsafe { signal::sigaction(sig, &action)? };
+    }
+    Ok(())
+}
+
+pub fn requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+// Shuts down like on a signal, e.g. when a budget is used up. Only the first reason is kept.
+pub fn stop(reason: String) {
+    let _ = STOP_REASON.set(reason);
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+pub fn stop_reason() -> Option<&'static str> {
+    STOP_REASON.get().map(String::as_str)
+}
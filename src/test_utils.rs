@@ -0,0 +1,64 @@
+use crate::config::Config;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+// A solver which always reports the same metrics
+pub const CONSTANT_SOLVER: &str = "#!/bin/sh\necho 'SPLITS DATA' > \"$2\"\necho '{\"time\": 1.5}' >> \"$2\"\n";
+
+// A fresh directory for the test called `name`
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("splits_test_{}_{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Sets up a run over a two variable formula with `solver_script` as the solver.
+// `extra_settings` are appended to the config.
+pub fn test_config(name: &str, solver_script: &str, extra_settings: &str) -> Config {
+    Config::parse_config(&test_config_string(name, solver_script, extra_settings)).unwrap()
+}
+
+// The config text of `test_config`, for tests of settings which are rejected
+pub fn test_config_string(name: &str, solver_script: &str, extra_settings: &str) -> String {
+    let dir = test_dir(name);
+    fs::create_dir_all(dir.join("out/logs")).unwrap();
+    fs::create_dir_all(dir.join("tmp")).unwrap();
+
+    let solver = dir.join("solver.sh");
+    fs::write(&solver, solver_script).unwrap();
+    fs::set_permissions(&solver, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.join("f.cnf"), "p cnf 2 1\n1 2 0\n").unwrap();
+
+    format!(
+        "variables: 1 2\nsolver: {}\ncnf: {}\noutput dir: {}\ntmp dir: {}\nevaluation metric: time\ncutoff: 1\nthread count: 1\n{}",
+        solver.display(),
+        dir.join("f.cnf").display(),
+        dir.join("out").display(),
+        dir.join("tmp").display(),
+        extra_settings,
+    )
+}
+
+// Tests which stop the run change process-wide state, so they run alone in a child process
+// of the test binary. Returns whether this is that child, where the test should go ahead.
+pub fn isolated(test: &str) -> bool {
+    if std::env::var_os("SPLITS_ISOLATED_TEST").is_some() {
+        return true;
+    }
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args([test, "--exact", "--nocapture"])
+        .env("SPLITS_ISOLATED_TEST", "1")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success() && stdout.contains("1 passed"),
+        "{test} failed in its own process:\n{stdout}\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    false
+}